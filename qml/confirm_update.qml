@@ -0,0 +1,37 @@
+import QtQuick.Controls
+import QtQuick.Window
+
+Window {
+    title: "Package Assistant"
+    visible: true
+    height: 160
+    width: 400
+
+    // Closing the window postpones the update
+    onClosing: Qt.exit(1)
+
+    Column {
+        anchors.horizontalCenter: parent.horizontalCenter
+        anchors.verticalCenter: parent.verticalCenter
+        spacing: 10
+
+        Label {
+            text: "Updates are available. Do you want to install them now?"
+        }
+
+        Row {
+            anchors.horizontalCenter: parent.horizontalCenter
+            spacing: 10
+
+            Button {
+                text: "Update now"
+                onClicked: Qt.exit(0)
+            }
+
+            Button {
+                text: "Later"
+                onClicked: Qt.exit(1)
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -14,4 +14,16 @@ pub fn start_app() {
     if let Some(app) = app.as_mut() {
         app.exec();
     }
-}
\ No newline at end of file
+}
+/// Shows a dialog asking whether to install the available updates now, and returns whether the user accepted
+pub fn confirm_update() -> bool {
+    let mut app = QGuiApplication::new();
+    let mut engine = QQmlApplicationEngine::new();
+
+    if let Some(engine) = engine.as_mut() {
+        engine.load(&QUrl::from("qrc:/confirm_update.qml"));
+    }
+
+    // The dialog exits with 0 if the update is accepted, and the window being closed counts as "later"
+    app.as_mut().is_some_and(|app| app.exec() == 0)
+}
@@ -1,21 +1,39 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, IsTerminal, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use package::ChangelogQuery;
-use clap::{Parser, Subcommand};
-use storage::{Config, Data, TomlStorage};
+use package::{ChangelogQuery, PackageManager, PackageUpdateItem, UpdateBundle, UpdateOptions, UpdateSummary};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use chrono::DateTime;
+use clap_complete::Shell;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use output::{colorize, ChangelogList, ChangelogSummary, OutputFormat, Render, RepoList, UpdateList, ANSI_RED};
+use storage::{Config, Data, DownloadStatus, TomlStorage, UpdateLock};
 
 mod package;
 mod storage;
+mod output;
 
 #[cfg(feature = "gui")]
 mod gui;
 
+const DOWNLOAD_LOG_FILE_NAME: &str = "download.log";
+const PENDING_PACKAGES_DIR_NAME: &str = "pending-packages";
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     StorageError(storage::Error),
-    PackageManagerError(package::Error)
+    PackageManagerError(package::Error),
+    IO(std::io::Error),
+    JSONError(serde_json::Error),
+    SelfTestFailed(usize),
+    UnsupportedFormat(OutputFormat)
 }
 
 impl From<storage::Error> for Error {
@@ -30,11 +48,40 @@ impl From<package::Error> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::IO(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::JSONError(value)
+    }
+}
+
+impl Error {
+    /// A stable identifier of the kind of error, as printed with `--format json`
+    fn code(&self) -> &'static str {
+        match self {
+            Error::StorageError(err) => err.code(),
+            Error::PackageManagerError(err) => err.code(),
+            Error::IO(_) => "IO",
+            Error::JSONError(_) => "JSON",
+            Error::SelfTestFailed(_) => "SELF_TEST_FAILED",
+            Error::UnsupportedFormat(_) => "UNSUPPORTED_FORMAT"
+        }
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::StorageError(err) => Some(err),
-            Error::PackageManagerError(err) => Some(err)
+            Error::PackageManagerError(err) => Some(err),
+            Error::IO(err) => Some(err),
+            Error::JSONError(err) => Some(err),
+            Error::SelfTestFailed(_) | Error::UnsupportedFormat(_) => None
         }
     }
 }
@@ -43,16 +90,140 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::StorageError(err) => err.fmt(f),
-            Error::PackageManagerError(err) => err.fmt(f)
+            Error::PackageManagerError(err) => err.fmt(f),
+            Error::IO(err) => err.fmt(f),
+            Error::JSONError(err) => err.fmt(f),
+            Error::SelfTestFailed(count) => write!(f, "{} self-test steps failed", count),
+            Error::UnsupportedFormat(format) => write!(f, "the {} format is not supported by this command", format.as_str())
         }
     }
 }
 
 #[derive(Debug, Parser)]
-#[command(name = "package-assistant")]
+#[command(name = "package-assistant", version, about = "Checks for, downloads and applies system package updates")]
 struct Cli {
     #[command(subcommand)]
     command: Command,
+    #[arg(long = "no-elevate", global = true, help = "Runs commands that need root privileges without pkexec or sudo, e.g. in a container. This is automatic when running as root.")]
+    no_elevate: bool,
+    #[arg(long = "format", short = 'f', global = true, value_enum, default_value_t = OutputFormat::Text, help = "The output format. Commands that don't support it fail with an error.")]
+    format: OutputFormat,
+    #[arg(long = "pretty", global = true, help = "Indents the JSON format over several lines for reading, instead of a single line for piping.")]
+    pretty: bool
+}
+
+#[derive(Debug, Args)]
+struct CheckUpdateArgs {
+    #[arg(long = "download", short = 'd', help = "If there are pending updates, downloads and caches packages locally.")]
+    download: bool,
+    #[arg(long = "flat", help = "Lists the updates without grouping them by category.")]
+    flat: bool,
+    #[arg(long = "foreground", help = "Waits for the download to finish, even if download_in_background is set in settings.")]
+    foreground: bool,
+    #[arg(long = "repo-refresh-age", value_name = "MINUTES", help = "Skips refreshing the repositories if they were refreshed within this many minutes. Overrides repo_refresh_age in settings.")]
+    repo_refresh_age: Option<u32>,
+    #[arg(long = "porcelain", conflicts_with_all = ["download", "foreground", "flat"], help = "Same as --format porcelain: prints one update per line as tab-separated name, old version and new version, with '-' for unknown versions. This format is stable for scripts.")]
+    porcelain: bool,
+    #[arg(long = "to", value_name = "DIR", requires = "download", help = "Downloads the packages into this directory instead of the package manager's cache, and lists their changelogs.")]
+    to: Option<PathBuf>,
+    #[arg(long = "output", short = 'o', value_name = "FILE", help = "Writes the list of updates to the file instead of stdout, in the selected format.")]
+    output: Option<PathBuf>,
+    #[arg(long = "arch", help = "Only lists updates for the given architecture, e.g. x86_64. Architecture independent (noarch) updates are always listed.")]
+    arch: Option<String>,
+    #[arg(long = "limit", value_name = "N", help = "Only lists the first N updates in the text format, followed by how many more there are. The total is still reported.")]
+    limit: Option<usize>
+}
+
+#[derive(Debug, Args)]
+struct UpdateArgs {
+    #[arg(long = "noconfirm", short = 'y', help = "Runs the update in a non-interactive mode, and attempts to solve conflicts automatically.")]
+    no_confirm: bool,
+    #[arg(long = "exclude", short = 'x', help = "Excludes packages matching the pattern from this update. Can be repeated.")]
+    exclude: Vec<String>,
+    #[arg(long = "dist", help = "Runs a distribution upgrade (e.g. zypper dup) instead of a regular update, which may change vendors, downgrade or remove packages.")]
+    dist: bool,
+    #[arg(long = "assume-no", conflicts_with = "offline", help = "Shows the proposed transaction and any conflicts without applying the update.")]
+    assume_no: bool,
+    #[arg(long = "only-security", conflicts_with_all = ["dist", "assume_no"], help = "Only installs updates that fix security issues.")]
+    only_security: bool,
+    #[arg(long = "include-optional", help = "Also installs updates held back by default: newly recommended packages with zypper, and the best versions and obsoleting packages with dnf.")]
+    include_optional: bool,
+    #[arg(long = "offline", conflicts_with_all = ["dist", "only_security"], help = "Downloads the updates and schedules them to be installed on the next reboot instead of updating the running system.")]
+    offline: bool,
+    #[arg(long = "restart-services", help = "Restarts any services that are still using outdated libraries after the update.")]
+    restart_services: bool,
+    #[arg(long = "input-timeout", value_name = "SECONDS", conflicts_with = "no_confirm", help = "Stops waiting for an interactive update after this many seconds and exits with an error, leaving the update running. Guards against updates waiting for input in automation.")]
+    input_timeout: Option<u64>,
+    #[arg(long = "package", short = 'p', value_name = "NAME", conflicts_with_all = ["dist", "only_security", "offline", "assume_no"], help = "Only updates this package instead of all available updates. Can be repeated.")]
+    packages: Vec<String>,
+    #[arg(long = "confirm-each", requires = "packages", help = "Asks whether to include each package given with --package before updating. Ignored with --noconfirm.")]
+    confirm_each: bool,
+    #[arg(long = "fail-on-conflict", requires = "no_confirm", conflicts_with = "offline", help = "Fails instead of letting the package manager resolve conflicts by changing vendors, downgrading or removing packages.")]
+    fail_on_conflict: bool
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+struct ChangelogArgs {
+    #[command(subcommand)]
+    command: Option<ChangelogCommand>,
+    #[arg(long = "query", short = 'q', help = "Filters changelogs by package name")]
+    query: Option<String>,
+    #[arg(long = "fuzzy", help = "Also matches package names that are close to the query, e.g. to allow for typos.")]
+    fuzzy: bool,
+    #[arg(long = "from-file", help = "Only shows changelogs for the packages listed in the file, one name per line.")]
+    from_file: Option<PathBuf>,
+    #[arg(long = "installed-only", help = "Skips packages that aren't installed on this system, e.g. in a cache shared between machines.")]
+    installed_only: bool,
+    #[arg(long = "utc", help = "Shows changelog dates in UTC instead of the timezone from settings.")]
+    utc: bool,
+    #[arg(long = "threads", help = "Maximum number of threads reading packages. Overrides scan_threads in settings, where 0 means one per CPU.")]
+    threads: Option<usize>,
+    #[arg(long = "pending", help = "Shows the changelogs of available updates instead of cached packages, downloading them if needed.")]
+    pending: bool,
+    #[arg(long = "feed-per-entry", help = "With the atom format, makes every changelog entry a feed entry instead of every package.")]
+    feed_per_entry: bool,
+    #[arg(long = "grep", value_name = "PATTERN", help = "Only shows changelog entries whose text matches the pattern, e.g. a CVE number. The pattern is a regular expression, so plain text also matches.")]
+    grep: Option<String>,
+    #[arg(long = "since-last-update", help = "Only shows changelog entries written after the last update installed with package-assistant.")]
+    since_last_update: bool,
+    #[arg(long = "quiet", help = "Doesn't show the progress of scanning for packages.")]
+    quiet: bool,
+    #[arg(long = "no-index", help = "Reads every package instead of using the changelog index, which is otherwise updated on every run.")]
+    no_index: bool,
+    #[arg(long = "summary", help = "Only shows the number of changelog entries of each package and the date of the newest one.")]
+    summary: bool,
+    #[arg(long = "installed", value_name = "NAME", conflicts_with_all = ["pending", "package_file"], help = "Shows the changelog of an installed package from the package database, without any cached packages.")]
+    installed: Option<String>,
+    #[arg(long = "package-file", conflicts_with = "pending", help = "Shows the changelogs of a single package file instead of cached packages.")]
+    package_file: Option<PathBuf>
+}
+
+#[derive(Debug, Subcommand)]
+enum ChangelogCommand {
+    #[command(about = "Reads all cached packages into the changelog index, so that later changelog queries are fast")]
+    Index,
+    #[command(about = "Lists the changelog entries of the packages in a directory that aren't in another, e.g. two copies of the cache")]
+    Diff {
+        #[arg(help = "The directory with the older packages")]
+        old_dir: PathBuf,
+        #[arg(help = "The directory with the newer packages")]
+        new_dir: PathBuf
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum RepoCommand {
+    #[command(about = "Enables a repository")]
+    Enable {
+        #[arg(help = "The id or name of the repository, as shown by list-repos")]
+        name: String
+    },
+    #[command(about = "Disables a repository, so that no updates are installed from it")]
+    Disable {
+        #[arg(help = "The id or name of the repository, as shown by list-repos")]
+        name: String
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -61,24 +232,86 @@ enum Command {
     Init {
         #[arg(long = "config", short = 'c', help = "Copies the configuration from the provided file")]
         config: Option<PathBuf>,
+        #[arg(long = "migrate", conflicts_with = "config", help = "Rewrites the existing settings file in the current format instead of creating one")]
+        migrate: bool,
+        #[arg(long = "check", conflicts_with = "migrate", help = "Validates the configuration and shows where it would be written, without writing anything")]
+        check: bool,
+        #[arg(long = "print", conflicts_with_all = ["migrate", "check"], help = "Writes the configuration to stdout instead of the settings file, e.g. to review the defaults")]
+        print: bool
     },
     #[command(about = "Uses the system's package manager to check whether there are update available.")]
-    CheckUpdate {
-        #[arg(long = "download", short = 'd', help = "If there are pending updates, downloads and caches packages locally.")]
-        download: bool
-    },
+    CheckUpdate(CheckUpdateArgs),
     #[command(about = "Uses the system's package manager to run an update.")]
-    Update {
-        #[arg(long = "noconfirm", short = 'y', help = "Runs the update in a non-interactive mode, and attempts to solve conflicts automatically.")]
+    Update(UpdateArgs),
+    #[command(about = "Lists the changelogs for any cached packages")]
+    Changelog(ChangelogArgs),
+    #[command(about = "Shows the status of the last download of updates")]
+    DownloadStatus,
+    #[command(about = "Shows when the system was last updated and how many updates are available, without refreshing the repositories")]
+    Status,
+    #[command(hide = true)]
+    Download {
+        #[arg(long = "to")]
+        to: Option<PathBuf>
+    },
+    #[command(about = "Lists the repositories that updates come from, with their enabled state and priority")]
+    ListRepos,
+    #[command(about = "Enables or disables a repository")]
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommand
+    },
+    #[command(about = "Lists the patches needed by the system (zypper only)")]
+    ListPatches,
+    #[command(about = "Installs a single patch by name (zypper only)")]
+    ApplyPatch {
+        #[arg(help = "The name of the patch, as shown by list-patches")]
+        name: String,
+        #[arg(long = "noconfirm", short = 'y', help = "Installs the patch without asking for confirmation.")]
         no_confirm: bool
     },
-    #[command(about = "Lists the changelogs for any cached packages")]
-    Changelog {
-        #[arg(long = "query", short = 'q', help = "Filters changelogs by package name")]
-        query: Option<String>,
+    #[command(about = "Deletes downloaded packages from the cache")]
+    CleanCache {
+        #[arg(long = "older-than", value_name = "DURATION", value_parser = parse_duration, help = "Only deletes packages downloaded longer ago than this, e.g. 30d, 12h or 1w.")]
+        older_than: Option<std::time::Duration>,
+        #[arg(long = "keep-latest", help = "Keeps the most recently downloaded file of each package, regardless of its age.")]
+        keep_latest: bool
+    },
+    #[command(about = "Checks the signatures and digests of the cached packages")]
+    Verify,
+    #[command(about = "Checks that package-assistant is set up properly, without updating anything")]
+    SelfTest,
+    #[command(about = "Reports the versions of package-assistant and the package management tools it uses")]
+    Versions,
+    #[command(about = "Writes the available updates and cached changelogs to a JSON bundle, e.g. for an offline machine")]
+    Export {
+        #[arg(help = "The file to write the bundle to")]
+        output: PathBuf
+    },
+    #[command(about = "Displays the updates and changelogs from a bundle created by the export command")]
+    Report {
+        #[arg(help = "The bundle file to read")]
+        input: PathBuf
     },
+    #[command(about = "Writes a JSON Schema of the settings file to stdout, e.g. for validating it in an editor")]
+    SchemaConfig,
+    #[command(about = "Generates a completion script for the given shell and writes it to stdout")]
+    Completions {
+        #[arg(help = "The shell to generate completions for")]
+        shell: Shell
+    },
+    #[command(about = "Generates a man page and writes it to stdout")]
+    Manpage {
+        #[arg(long = "output", short = 'o', help = "Writes the man page to the provided file instead of stdout")]
+        output: Option<PathBuf>
+    },
+    #[command(about = "Keeps running in the background, checking for updates and asking whether to install them when there are any")]
+    Auto,
     #[cfg(feature = "gui")]
-    Gui,
+    Gui {
+        #[arg(long = "confirm-update", hide = true)]
+        confirm_update: bool
+    },
     #[cfg(debug_assertions)]
     #[command(about = "Verifies that package-assistant runs properly")]
     Test
@@ -86,18 +319,50 @@ enum Command {
 
 fn main() {
     let args = Cli::parse();
+    if args.no_elevate {
+        package::disable_privilege_elevation();
+    }
+    if args.pretty {
+        output::enable_pretty_json();
+    }
+
     let result = match args.command {
-        Command::Init { config: path_opt } => init(path_opt),
-        Command::CheckUpdate { download } => check_update(download),
-        Command::Update { no_confirm } => update(no_confirm),
-        Command::Changelog { query } => changelog(query),
+        Command::Init { config: path_opt, check: true, .. } => check_init(path_opt),
+        Command::Init { config: path_opt, print: true, .. } => print_init(path_opt),
+        Command::Init { migrate: true, .. } => migrate_config(),
+        Command::Init { config: path_opt, .. } => init(path_opt),
+        Command::CheckUpdate(check_update_args) => check_update(check_update_args, args.format),
+        Command::DownloadStatus => download_status(),
+        Command::Status => status(args.format),
+        Command::Download { to } => download(to.as_deref(), false),
+        Command::Update(args) => update(args),
+        Command::Changelog(changelog_args) => changelog(changelog_args, args.format),
+        Command::ListRepos => list_repos(args.format),
+        Command::Repo { command } => repo(command),
+        Command::ListPatches => list_patches(),
+        Command::ApplyPatch { name, no_confirm } => apply_patch(&name, no_confirm),
+        Command::CleanCache { older_than, keep_latest } => clean_cache(older_than, keep_latest),
+        Command::Verify => verify(),
+        Command::SelfTest => self_test(),
+        Command::Versions => versions(args.format),
+        Command::Export { output } => export(output),
+        Command::Report { input } => report(input),
+        Command::SchemaConfig => schema_config(),
+        Command::Completions { shell } => completions(shell),
+        Command::Manpage { output } => manpage(output),
+        Command::Auto => auto(),
         #[cfg(feature = "gui")]
-        Command::Gui => gui(),
+        Command::Gui { confirm_update } => gui(confirm_update),
         #[cfg(debug_assertions)]
         Command::Test => perform_test(),
     };
 
     match result {
+        Err(err) if args.format == OutputFormat::Json => {
+            let error_json = serde_json::json!({ "error": { "code": err.code(), "message": err.to_string() } });
+            eprintln!("{}", output::to_json(&error_json).unwrap_or_else(|_| error_json.to_string()));
+            std::process::exit(1);
+        },
         Err(err) => {
             eprintln!("Error: {}", err);
             std::process::exit(1);
@@ -117,49 +382,911 @@ fn init(path_opt: Option<PathBuf>) -> Result<()> {
     Ok(())
 }
 
-fn check_update(download: bool) -> Result<()> {
+/// Validates the configuration that `init` would write, either from the provided file or the default one, without
+/// touching the filesystem
+fn check_init(path_opt: Option<PathBuf>) -> Result<()> {
+    check_init_paths(path_opt, &Config::get_file_path()?, &Data::get_file_path()?)
+}
+
+/// Like `check_init`, but with the paths that the configuration and data would be written to
+fn check_init_paths(path_opt: Option<PathBuf>, config_path: &Path, data_path: &Path) -> Result<()> {
+    let config = get_init_config(path_opt)?;
+
+    println!("Would write configuration to {}{}", config_path.display(),
+        if config_path.exists() { " (already exists)" } else { "" });
+    println!("Would write data to {}{}", data_path.display(),
+        if data_path.exists() { " (already exists)" } else { "" });
+
+    config.validate()?;
+    println!("The configuration is valid.");
+    Ok(())
+}
+
+/// Prints the configuration that `init` would write, either the provided file in the current format or the defaults
+fn print_init(path_opt: Option<PathBuf>) -> Result<()> {
+    print!("{}", get_init_config(path_opt)?.to_toml_str()?);
+    Ok(())
+}
+
+/// Gets the configuration that `init` would write: the provided file migrated to the current format, or the defaults
+fn get_init_config(path_opt: Option<PathBuf>) -> Result<Config> {
+    match path_opt {
+        Some(path) => Ok(Config::from_toml_str(&std::fs::read_to_string(path)?)?),
+        None => Ok(Config::new())
+    }
+}
+
+fn migrate_config() -> Result<()> {
+    let path = Config::get_file_path()?;
+    if Config::migrate_file()? {
+        println!("Migrated {} to version {}", path.display(), storage::CURRENT_CONFIG_VERSION);
+    } else if !path.exists() {
+        println!("Nothing to migrate, {} doesn't exist and only the vendor defaults are used", path.display());
+    } else {
+        println!("{} is already up to date", path.display());
+    }
+
+    Ok(())
+}
+
+/// Writes the rendered `output` of a command to `path` instead of stdout, creating its directory if needed
+fn write_output_file(path: &Path, output: &str) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, format!("{}\n", output))?;
+
+    Ok(())
+}
+
+fn check_update(args: CheckUpdateArgs, format: OutputFormat) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let mut data = Data::fetch().unwrap_or_default();
+    let now = get_timestamp();
+    let refresh = args.repo_refresh_age.or(config.service.repo_refresh_age)
+        .is_none_or(|max_age| is_refresh_due(data.last_refresh_timestamp, max_age, now));
+
+    let mut updates = pkg_manager.check_update(refresh)?;
+    if refresh {
+        data.last_refresh_timestamp = now;
+        Data::save(data)?;
+    }
+
+    if let Some(ref arch) = args.arch {
+        updates = UpdateSummary::new(filter_updates_by_arch(updates.items, arch));
+    }
+
+    let format = if args.porcelain { OutputFormat::Porcelain } else { format };
+    let output = UpdateList { updates: &updates, group_by_category: !args.flat, limit: args.limit }.render(format)?;
+    if let Some(ref output_path) = args.output {
+        write_output_file(output_path, &output)?;
+    } else if !output.is_empty() {
+        println!("{}", output);
+    }
+
+    if updates.total == 0 {
+        return Ok(())
+    }
+
+    // Other formats are meant for scripts, so the messages about the download mustn't mix with the list on stdout
+    let print_notice = |message: &str| if format == OutputFormat::Text || args.output.is_some() {
+        println!("{}", message)
+    } else {
+        eprintln!("{}", message)
+    };
+
+    // The package manager may not run in the current directory, e.g. through pkexec
+    let download_dir = match args.to {
+        Some(ref dir) => {
+            std::fs::create_dir_all(dir)?;
+            Some(std::fs::canonicalize(dir)?)
+        },
+        None => None
+    };
+
+    let download_in_background = config.service.download_in_background && !args.foreground;
+    if download_in_background {
+        spawn_background_download(download_dir.as_deref())?;
+        print_notice("Downloading updates in the background. Run 'package-assistant download-status' to check on it.");
+    } else if args.download || config.service.download_in_background {
+        download(download_dir.as_deref(), format != OutputFormat::Text && args.output.is_none())?;
+        print_notice("Updates downloaded.");
+
+        if let Some(ref download_dir) = download_dir {
+            let results = pkg_manager.get_dir_changelog_results(&ChangelogQuery::default(), download_dir)?;
+            print_notice(&ChangelogList { config: &config.package, results: &results, feed_per_entry: false }.render_text());
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the updates for `arch`, along with architecture independent ones and ones whose architecture isn't known
+fn filter_updates_by_arch(items: Vec<PackageUpdateItem>, arch: &str) -> Vec<PackageUpdateItem> {
+    items.into_iter()
+        .filter(|item| item.arch.as_deref().is_none_or(|item_arch| item_arch == arch || item_arch == "noarch"))
+        .collect()
+}
+
+/// Checks whether more than `max_age` minutes have passed since the repositories were last refreshed
+fn is_refresh_due(last_refresh_timestamp: u64, max_age: u32, now: u64) -> bool {
+    now.saturating_sub(last_refresh_timestamp) >= u64::from(max_age) * 60
+}
+
+/// Runs the hidden `download` subcommand as a detached process, logging its output to the data directory
+fn spawn_background_download(download_dir: Option<&Path>) -> Result<()> {
+    let mut log_path = Data::get_dir_path()?;
+    std::fs::create_dir_all(&log_path)?;
+    log_path.push(DOWNLOAD_LOG_FILE_NAME);
+    let log_file = std::fs::File::create(&log_path)?;
+
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command.arg("download");
+    if !package::is_privilege_elevation_enabled() {
+        command.arg("--no-elevate");
+    }
+    if let Some(download_dir) = download_dir {
+        command.arg("--to").arg(download_dir);
+    }
+
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file)
+        // Keep the download running if the terminal is interrupted
+        .process_group(0)
+        .spawn()?;
+
+    Ok(())
+}
+
+/// Downloads the available updates, recording the status in the data file. The package manager's progress is printed
+/// to stdout, or to stderr with `progress_on_stderr` so that it doesn't mix with machine-readable output.
+fn download(download_dir: Option<&Path>, progress_on_stderr: bool) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let _lock = UpdateLock::acquire()?;
+
+    set_download_status(DownloadStatus::Running)?;
+    let result = pkg_manager.download_update(download_dir, true, Some(&mut |line| if progress_on_stderr {
+        eprintln!("{}", line)
+    } else {
+        println!("{}", line)
+    }));
+    set_download_status(if result.is_ok() { DownloadStatus::Succeeded } else { DownloadStatus::Failed })?;
+
+    Ok(result?)
+}
+
+fn set_download_status(status: DownloadStatus) -> Result<()> {
+    let mut data = Data::fetch().unwrap_or_default();
+    data.download_status = status;
+    if status != DownloadStatus::Running {
+        data.download_timestamp = get_timestamp();
+    }
+
+    Ok(Data::save(data)?)
+}
+
+fn download_status() -> Result<()> {
+    let data = Data::fetch()?;
+    let mut log_path = Data::get_dir_path()?;
+    log_path.push(DOWNLOAD_LOG_FILE_NAME);
+
+    match data.download_status {
+        DownloadStatus::None => println!("No updates have been downloaded yet."),
+        // A download that's still running holds the lock, so it can only be acquired if the download was interrupted
+        DownloadStatus::Running if UpdateLock::acquire().is_ok() => println!("The last download was interrupted."),
+        DownloadStatus::Running => println!("Updates are being downloaded."),
+        DownloadStatus::Succeeded => println!("Updates were downloaded {}.", format_time_ago(data.download_timestamp)),
+        DownloadStatus::Failed => println!("The last download failed {}. See {} for details.", format_time_ago(data.download_timestamp), log_path.display())
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct StatusReport {
+    /// Unix timestamp of the last update, or 0 if the system was never updated
+    last_update_timestamp: u64,
+    last_update_iso: Option<String>,
+    seconds_since: Option<u64>,
+    pending_updates: usize
+}
+
+impl StatusReport {
+    fn new(data: &Data, pending_updates: usize, now: u64) -> Self {
+        let last_update_timestamp = data.update_timestamp;
+        let last_update = (last_update_timestamp > 0)
+            .then(|| i64::try_from(last_update_timestamp).ok().and_then(|timestamp| DateTime::from_timestamp(timestamp, 0)))
+            .flatten();
+
+        StatusReport {
+            last_update_timestamp,
+            last_update_iso: last_update.map(|date| date.to_rfc3339()),
+            seconds_since: last_update.map(|_| now.saturating_sub(last_update_timestamp)),
+            pending_updates
+        }
+    }
+}
+
+impl Render for StatusReport {
+    fn render_text(&self) -> String {
+        let last_update = match self.last_update_timestamp {
+            0 => String::from("The system has not been updated by package-assistant yet."),
+            timestamp => format!("The system was last updated {}.", format_time_ago(timestamp))
+        };
+
+        format!("{}\n{} updates available.", last_update, self.pending_updates)
+    }
+
+    fn render_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+/// Records that an update was installed. Only called once the update command succeeded, so that a failed or
+/// cancelled update doesn't hide changelogs from `changelog --since-last-update`.
+fn record_update() -> Result<()> {
+    let mut data = Data::fetch_or_default()?;
+    data.update_timestamp = get_timestamp();
+    Data::save(data)?;
+    Ok(())
+}
+
+/// The timestamp of the last update installed with package-assistant, or `None` if there hasn't been one
+fn get_last_update_timestamp(data: &Data) -> Option<u64> {
+    Some(data.update_timestamp).filter(|timestamp| *timestamp > 0)
+}
+
+fn status(format: OutputFormat) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    // Without any data, the system hasn't been updated by package-assistant yet
+    let data = Data::fetch_or_default()?;
+    // Uses the cached repository metadata, so that the status is quick enough for shell prompts
+    let pending_updates = pkg_manager.check_update(false)?.total;
+    let report = StatusReport::new(&data, pending_updates, get_timestamp());
+
+    println!("{}", report.render(format)?);
+    Ok(())
+}
+
+fn update(args: UpdateArgs) -> Result<()> {
     let config = Config::fetch()?;
     let pkg_manager = package::get_package_manager(&config.package)?;
-    let updates = pkg_manager.check_update()?;
+    let lock = UpdateLock::acquire()?;
+    let mut update_options = UpdateOptions {
+        exclude: args.exclude,
+        include_optional: args.include_optional,
+        fail_on_conflict: args.fail_on_conflict,
+        packages: Vec::new()
+    };
+    if let Some(input_timeout) = args.input_timeout {
+        package::set_interactive_timeout(std::time::Duration::from_secs(input_timeout));
+    }
+
+    if args.assume_no {
+        let preview = pkg_manager.preview_update(&update_options, args.dist, true)?;
+        println!("{}", preview.transaction.trim_end());
+
+        if preview.conflicts.is_empty() {
+            println!("\nNo conflicts found.");
+        } else {
+            println!("\nConflicts:");
+            for conflict in preview.conflicts {
+                println!("{}", conflict);
+            }
+        }
+
+        return Ok(())
+    } else if args.offline {
+        pkg_manager.offline_update(&update_options, true)?;
+        println!("Updates will be installed on the next reboot.");
+        return Ok(())
+    }
+
+    update_options.packages = if args.confirm_each && !args.no_confirm {
+        match select_packages(&args.packages, &mut std::io::stdin().lock())? {
+            Some(packages) if !packages.is_empty() => packages,
+            _ => {
+                println!("No packages selected.");
+                return Ok(())
+            }
+        }
+    } else {
+        args.packages
+    };
+
+    let result = install_updates(&config, pkg_manager.as_ref(), &update_options, args.only_security, args.restart_services, || {
+        if args.only_security {
+            pkg_manager.do_security_update(&update_options, !args.no_confirm, true)
+        } else if args.dist {
+            pkg_manager.dist_upgrade(&update_options, !args.no_confirm, true)
+        } else {
+            pkg_manager.do_update(&update_options, !args.no_confirm, true)
+        }
+    });
+
+    // An update that timed out is left running, so other updates have to wait until its transaction is done
+    if let Err(Error::PackageManagerError(package::Error::InputTimeout(_, pid))) = result {
+        lock.keep_while_running(pid)?;
+    }
+
+    result
+}
+
+/// Installs updates with `run_update`, along with everything that surrounds it: the pre-update hook and snapshot
+/// before, and afterwards recording the update, the post-update hook, and reporting whether a reboot is required and
+/// which services use outdated libraries. Those services are restarted if `restart_services` is set. `options` and
+/// `only_security` tell which of the pending updates `run_update` installs.
+fn install_updates(config: &Config, pkg_manager: &dyn PackageManager, options: &UpdateOptions, only_security: bool,
+    restart_services: bool, run_update: impl FnOnce() -> std::result::Result<(), package::Error>) -> Result<()> {
+    if let Some(ref command) = config.package.pre_update_command {
+        pkg_manager.run_hook(command, true)?;
+    }
+
+    if config.package.snapshot_before_update {
+        create_snapshot(pkg_manager)?;
+    }
+
+    // The pending updates are only known before updating, so they're listed in case the package manager can't tell
+    // afterwards whether a reboot is required
+    let pending_updates = match pkg_manager.reboot_required() {
+        None => pkg_manager.check_update(false).ok()
+            .map(|summary| filter_pending_updates(summary.items, &config.package.ignored_packages, options, only_security)),
+        Some(_) => None
+    };
+
+    run_update()?;
+    record_update()?;
+
+    if let Some(ref command) = config.package.post_update_command {
+        pkg_manager.run_hook(command, true)?;
+    }
+
+    let reboot_required = pkg_manager.reboot_required()
+        .or_else(|| pending_updates.map(|items| pkg_manager.reboot_triggered_by(&items)));
+    match reboot_required {
+        Some(true) => println!("Reboot required to finish applying updates."),
+        Some(false) => (),
+        None => println!("Could not determine whether a reboot is required.")
+    }
+
+    match pkg_manager.services_needing_restart() {
+        Ok(services) if !services.is_empty() => {
+            println!("Services using outdated libraries:");
+            for service in &services {
+                println!("{}", service);
+            }
+
+            if restart_services {
+                pkg_manager.restart_services(&services, true)?;
+                println!("Services restarted.");
+            }
+        },
+        Ok(_) => (),
+        Err(err) if restart_services => return Err(err.into()),
+        Err(_) => ()
+    }
+
+    Ok(())
+}
+
+/// Keeps the pending updates that an update with `options` installs: the chosen packages if any, only security
+/// updates if `only_security` is set, and neither `ignored_packages` nor excluded ones
+fn filter_pending_updates(items: Vec<PackageUpdateItem>, ignored_packages: &[String], options: &UpdateOptions,
+    only_security: bool) -> Vec<PackageUpdateItem> {
+    items.into_iter()
+        .filter(|item| options.packages.is_empty()
+            || options.packages.iter().any(|name| name == &item.name || name == item.base_name()))
+        .filter(|item| !only_security || item.category.as_deref() == Some("security"))
+        .filter(|item| !ignored_packages.iter().chain(options.exclude.iter())
+            .any(|pattern| package::matches_pattern(item.base_name(), pattern)))
+        .collect()
+}
+
+/// Asks whether to update each of `names`, reading the answers from `input`. `None` means that nothing should be updated.
+fn select_packages<R: BufRead>(names: &[String], input: &mut R) -> Result<Option<Vec<String>>> {
+    let mut selected = Vec::new();
+
+    for name in names {
+        loop {
+            print!("Update {}? [y/n/q] ", name);
+            std::io::stdout().flush()?;
+
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 {
+                return Ok(None)
+            }
+
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => {
+                    selected.push(name.clone());
+                    break
+                },
+                "n" | "no" => break,
+                "q" | "quit" => return Ok(None),
+                _ => println!("Please answer y, n or q.")
+            }
+        }
+    }
+
+    Ok(Some(selected))
+}
+
+/// Creates a snapshot before updating and records its number. If snapper isn't available, the update goes ahead without one.
+fn create_snapshot(pkg_manager: &dyn PackageManager) -> Result<()> {
+    match pkg_manager.create_snapshot(true) {
+        Ok(snapshot_number) => {
+            if let Some(number) = snapshot_number {
+                println!("Created snapshot {}.", number);
+            }
+
+            let mut data = Data::fetch().unwrap_or_default();
+            data.snapshot_number = snapshot_number;
+            Ok(Data::save(data)?)
+        },
+        Err(package::Error::Unsupported(_)) => {
+            eprintln!("Warning: snapper is not installed, updating without a snapshot.");
+            Ok(())
+        },
+        Err(err) => Err(err.into())
+    }
+}
+
+fn changelog(args: ChangelogArgs, format: OutputFormat) -> Result<()> {
+    match args.command {
+        Some(ChangelogCommand::Diff { old_dir, new_dir }) => return changelog_diff(&old_dir, &new_dir, format, args.feed_per_entry),
+        Some(ChangelogCommand::Index) => return changelog_index(args.quiet),
+        None => ()
+    }
+
+    let mut config = Config::fetch()?;
+    if args.threads.is_some() {
+        config.package.scan_threads = args.threads;
+    }
+    if args.utc {
+        config.package.changelog_timezone = Some(String::from("UTC"));
+    }
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let names = args.from_file.as_deref().map(read_package_list).transpose()?;
+    let fuzzy_max_distance = args.fuzzy.then_some(config.package.fuzzy_max_distance);
+    let installed = args.installed_only.then(|| pkg_manager.installed_packages()).transpose()?;
+    // Without a recorded update, only the installed versions limit which entries are shown
+    let since = if args.since_last_update { get_last_update_timestamp(&Data::fetch_or_default()?) } else { None };
+    let show_progress = !args.quiet && std::io::stderr().is_terminal();
+    let changelog_query = ChangelogQuery { name: args.query, names, fuzzy_max_distance, installed, since, skip_index: args.no_index, show_progress };
+
+    let results = if let Some(ref name) = args.installed {
+        pkg_manager.get_installed_changelog_results(&changelog_query, name)?
+    } else if let Some(ref package_file) = args.package_file {
+        pkg_manager.get_file_changelog_results(&changelog_query, package_file)?
+    } else if args.pending {
+        let mut pending_path = storage::get_cache_dir_path()?;
+        pending_path.push(PENDING_PACKAGES_DIR_NAME);
+        pkg_manager.get_pending_changelog_results(&changelog_query, &pending_path)?
+    } else {
+        match pkg_manager.get_cached_changelog_results(&changelog_query) {
+            // An empty cache isn't an error, it just means that nothing has been downloaded yet
+            Err(package::Error::NoPackagesInDirectory) => {
+                let message = "No packages have been downloaded yet. Run `package-assistant check-update --download` to download the available updates first.";
+                if format == OutputFormat::Text {
+                    println!("{}", message);
+                    return Ok(())
+                }
+
+                eprintln!("{}", message);
+                Vec::new()
+            },
+            result => result?
+        }
+    };
+
+    if let (Some(query_name), Some(_)) = (&changelog_query.name, fuzzy_max_distance) {
+        let mut interpreted_names = results.iter()
+            .map(|result| result.name.as_str())
+            .filter(|name| !name.starts_with(query_name.as_str()))
+            .collect::<Vec<&str>>();
+        interpreted_names.sort();
+        interpreted_names.dedup();
+
+        if !interpreted_names.is_empty() {
+            eprintln!("Interpreted '{}' as: {}", query_name, interpreted_names.join(", "));
+        }
+    }
+
+    if let Some(ref names) = changelog_query.names {
+        let mut unmatched_names = names.iter()
+            .filter(|name| !results.iter().any(|result| &result.name == *name))
+            .collect::<Vec<&String>>();
+        unmatched_names.sort();
+
+        for name in unmatched_names {
+            eprintln!("Warning: no changelogs found for '{}'", name);
+        }
+    }
+
+    let results = match args.grep {
+        Some(ref pattern) => {
+            let pattern = Regex::new(pattern).map_err(package::Error::from)?;
+            let mut results = package::grep_changelog_results(results, &pattern);
+            if format == OutputFormat::Text {
+                for entry in results.iter_mut().flat_map(|result| result.changelogs.iter_mut()) {
+                    entry.description = pattern.replace_all(&entry.description, |captures: &Captures| {
+                        colorize(&captures[0], ANSI_RED)
+                    }).into_owned();
+                }
+            }
+
+            results
+        },
+        None => results
+    };
 
-    if updates.is_empty() {
-        println!("No updates available.");
+    if args.summary {
+        let summary = package::summarize_changelog_results(&results);
+        println!("{}", ChangelogSummary { config: &config.package, summary: &summary }.render(format)?);
         return Ok(())
+    }
+
+    let changelogs = ChangelogList { config: &config.package, results: &results, feed_per_entry: args.feed_per_entry };
+    println!("{}", changelogs.render(format)?);
+    Ok(())
+}
+
+fn changelog_index(quiet: bool) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let package_count = pkg_manager.index_cached_changelogs(!quiet && std::io::stderr().is_terminal())?;
+
+    println!("Indexed the changelogs of {} packages.", package_count);
+    Ok(())
+}
+
+fn changelog_diff(old_dir: &Path, new_dir: &Path, format: OutputFormat, feed_per_entry: bool) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let changelog_query = ChangelogQuery::default();
+    let get_results = |path: &Path| match pkg_manager.get_dir_changelog_results(&changelog_query, path) {
+        Err(package::Error::NoChangelogsInDirectory | package::Error::NoPackagesInDirectory) => Ok(Vec::new()),
+        result => result
+    };
+
+    let old_results = get_results(old_dir)?;
+    let new_results = get_results(new_dir)?;
+
+    let mut removed_names = old_results.iter()
+        .map(|result| result.name.as_str())
+        .filter(|name| !new_results.iter().any(|result| result.name == *name))
+        .collect::<Vec<&str>>();
+    removed_names.sort();
+    removed_names.dedup();
+
+    for name in removed_names {
+        eprintln!("Note: '{}' is only in {}", name, old_dir.display());
+    }
+
+    let results = package::diff_changelog_results(&old_results, new_results);
+    if format == OutputFormat::Text && results.is_empty() {
+        println!("No new changelog entries.");
+    } else {
+        let changelogs = ChangelogList { config: &config.package, results: &results, feed_per_entry };
+        println!("{}", changelogs.render(format)?);
+    }
+    Ok(())
+}
+
+/// Reads package names from a file with one name per line, ignoring blank lines and `#` comments
+fn read_package_list(path: &Path) -> Result<HashSet<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    let names = contents.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+
+    Ok(names)
+}
+
+fn clean_cache(older_than: Option<std::time::Duration>, keep_latest: bool) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let _lock = UpdateLock::acquire()?;
+    let cleanup = pkg_manager.clean_cache(older_than, keep_latest)?;
+
+    println!("Removed {} packages, freeing {}.", cleanup.removed_count, format_bytes(cleanup.freed_bytes));
+    for (path, err) in &cleanup.failed {
+        eprintln!("Couldn't remove {}: {}", path.display(), err);
+    }
+
+    if cleanup.failed.is_empty() {
+        Ok(())
+    } else {
+        Err(package::Error::CacheCleanupFailed(cleanup.failed.len()).into())
+    }
+}
+
+fn verify() -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let results = pkg_manager.verify_cache()?;
+
+    for result in &results {
+        if result.valid {
+            println!("OK      {}", result.path.display());
+        } else {
+            println!("FAILED  {}: {}", result.path.display(), result.message);
+        }
+    }
+
+    let failed_count = results.iter().filter(|result| !result.valid).count();
+    println!("\n{} packages passed, {} failed.", results.len() - failed_count, failed_count);
+
+    if failed_count > 0 {
+        Err(package::Error::VerificationFailed(failed_count).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn list_repos(format: OutputFormat) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let repos = pkg_manager.list_repositories()?;
+
+    println!("{}", RepoList { repos: &repos }.render(format)?);
+    Ok(())
+}
+
+fn repo(command: RepoCommand) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let (name, enabled) = match command {
+        RepoCommand::Enable { name } => (name, true),
+        RepoCommand::Disable { name } => (name, false)
+    };
+
+    pkg_manager.set_repository_enabled(&name, enabled, true)?;
+    println!("{} repository {}.", if enabled { "Enabled" } else { "Disabled" }, name);
+    Ok(())
+}
+
+fn list_patches() -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let patches = pkg_manager.list_patches()?;
+
+    if patches.is_empty() {
+        println!("No patches needed.");
     } else {
-        println!("Available updates:");
-        for update in updates {
-            println!("{}", update);
+        println!("Needed patches:");
+        for patch in &patches {
+            println!("{}", patch);
         }
     }
 
-    if download || config.service.download_in_background {
-        pkg_manager.download_update(true)?;
-        println!("Updates downloaded.");
+    Ok(())
+}
+
+fn apply_patch(name: &str, no_confirm: bool) -> Result<()> {
+    let config = Config::fetch()?;
+    let pkg_manager = package::get_package_manager(&config.package)?;
+    let _lock = UpdateLock::acquire()?;
+
+    pkg_manager.apply_patch(name, !no_confirm, true)?;
+
+    match pkg_manager.reboot_required() {
+        Some(true) => println!("Reboot required to finish applying the patch."),
+        Some(false) => (),
+        None => println!("Could not determine whether a reboot is required.")
     }
 
     Ok(())
 }
 
-fn update(no_confirm: bool) -> Result<()> {
+/// Runs each step that the other commands depend on and reports whether it works. Nothing is installed or downloaded.
+fn self_test() -> Result<()> {
+    let mut failed_count = 0;
+    let mut report_step = |step: &str, error: Option<String>| match error {
+        None => println!("OK      {}", step),
+        Some(error) => {
+            failed_count += 1;
+            println!("FAILED  {}: {}", step, error);
+        }
+    };
+
+    let config = Config::fetch();
+    report_step("Read settings", config.as_ref().err().map(|err| err.to_string()));
+
+    if let Ok(ref config) = config {
+        let pkg_manager = package::get_package_manager(&config.package);
+        report_step("Find package manager", pkg_manager.as_ref().err().map(|err| err.to_string()));
+
+        if let Ok(ref pkg_manager) = pkg_manager {
+            // Uses the cached metadata, since refreshing the repositories isn't a no-op
+            let updates = pkg_manager.check_update(false);
+            report_step("Check for updates", updates.err().map(|err| err.to_string()));
+        }
+
+        let cache_error = match config.package.cached_package_path {
+            Some(ref paths) => {
+                let errors = paths.paths().iter()
+                    .filter(|path| package::parse_remote_path(path).is_none())
+                    .filter_map(|path| std::fs::read_dir(path).err().map(|err| format!("{}: {}", path.display(), err)))
+                    .collect::<Vec<String>>();
+                (!errors.is_empty()).then(|| errors.join("; "))
+            },
+            None => Some(package::Error::UnkownCachedPackagePath.to_string())
+        };
+        report_step("Read package cache", cache_error);
+    }
+
+    if failed_count > 0 {
+        Err(Error::SelfTestFailed(failed_count))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct VersionReport {
+    package_assistant: &'static str,
+    package_manager: Option<&'static str>,
+    /// Versions of the package management tools, or `None` for tools that aren't installed
+    tools: BTreeMap<&'static str, Option<String>>
+}
+
+impl Render for VersionReport {
+    fn render_text(&self) -> String {
+        let mut lines = vec![
+            format!("package-assistant: {}", self.package_assistant),
+            format!("configured package manager: {}", self.package_manager.unwrap_or("none"))
+        ];
+        lines.extend(self.tools.iter().map(|(tool, version)| format!("{}: {}", tool, version.as_deref().unwrap_or("not found"))));
+
+        lines.join("\n")
+    }
+
+    fn render_json(&self) -> Result<String> {
+        output::to_json(self)
+    }
+}
+
+fn versions(format: OutputFormat) -> Result<()> {
+    let package_manager = Config::fetch().ok()
+        .and_then(|config| config.package.package_manager)
+        .map(|package_manager| package_manager.as_str());
+    let tools = ["zypper", "dnf", "rpm"].into_iter()
+        .map(|tool| (tool, package::tool_version(tool)))
+        .collect::<BTreeMap<&str, Option<String>>>();
+    let report = VersionReport { package_assistant: env!("CARGO_PKG_VERSION"), package_manager, tools };
+
+    println!("{}", report.render(format)?);
+    Ok(())
+}
+
+fn export(output: PathBuf) -> Result<()> {
     let config = Config::fetch()?;
     let pkg_manager = package::get_package_manager(&config.package)?;
+    let bundle = UpdateBundle::create(pkg_manager.as_ref())?;
+    bundle.write(&output)?;
+
+    if let Some(s) = output.to_str() {
+        println!("Wrote {} updates and {} changelogs to {}", bundle.updates.len(), bundle.changelogs.len(), s);
+    }
+
+    Ok(())
+}
+
+fn report(input: PathBuf) -> Result<()> {
+    let bundle = UpdateBundle::read(&input)?;
+    let config = Config::fetch().unwrap_or_default();
+    print_updates(&UpdateSummary::new(bundle.updates), true);
+
+    if !bundle.changelogs.is_empty() {
+        println!("\nChangelog:\n{}", package::format_changelog_results(&config.package, &bundle.changelogs));
+    }
+
+    Ok(())
+}
+
+fn schema_config() -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&storage::get_config_schema())?);
+    Ok(())
+}
+
+fn completions(shell: Shell) -> Result<()> {
+    let mut command = Cli::command();
+    let name = command.get_name().to_owned();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+    Ok(())
+}
 
-    pkg_manager.do_update(!no_confirm, true)?;
+fn manpage(output: Option<PathBuf>) -> Result<()> {
+    let man = clap_mangen::Man::new(Cli::command());
+
+    if let Some(path) = output {
+        let mut file = std::fs::File::create(path)?;
+        man.render(&mut file)?;
+    } else {
+        man.render(&mut std::io::stdout())?;
+    }
 
     Ok(())
 }
 
-fn changelog(query: Option<String>) -> Result<()> {
+/// Checks for updates every `update_check_frequency` minutes. When there are any, they're downloaded first if
+/// `download_in_background` is set, and then installed if the user accepts the confirmation dialog.
+fn auto() -> Result<()> {
     let config = Config::fetch()?;
+    if !config.service.enable_service {
+        println!("The service is disabled in settings.");
+        return Ok(())
+    }
+
     let pkg_manager = package::get_package_manager(&config.package)?;
-    let ref changelog_query = ChangelogQuery { name: query };
-    let changelogs = pkg_manager.get_cached_changelogs(changelog_query)?;
-    println!("{}", changelogs);
+    let interval = std::time::Duration::from_secs(u64::from(config.service.update_check_frequency.max(1)) * 60);
+
+    loop {
+        // A failed check shouldn't stop the service, since the next one may succeed
+        if let Err(err) = auto_update(&config, pkg_manager.as_ref()) {
+            eprintln!("Error: {}", err);
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+fn auto_update(config: &Config, pkg_manager: &dyn PackageManager) -> Result<()> {
+    let updates = pkg_manager.check_update(true)?;
+    print_updates(&updates, true);
+    if updates.total == 0 {
+        return Ok(())
+    }
+
+    if config.service.download_in_background {
+        download(None, false)?;
+    }
+
+    if !confirm_update()? {
+        println!("Update postponed.");
+        return Ok(())
+    }
+
+    let _lock = UpdateLock::acquire()?;
+    let options = UpdateOptions::default();
+    install_updates(config, pkg_manager, &options, false, false, || pkg_manager.do_update(&options, false, true))?;
+    println!("Updates installed.");
+
     Ok(())
 }
 
+/// Asks whether to install the available updates in a dialog. Since a Qt application can only be created once per
+/// process, the dialog runs in a child process, which exits successfully if the update is accepted.
 #[cfg(feature = "gui")]
-fn gui() -> Result<()> {
+fn confirm_update() -> Result<bool> {
+    let status = std::process::Command::new(std::env::current_exe()?)
+        .args(["gui", "--confirm-update"])
+        .status()?;
+
+    Ok(status.success())
+}
+
+#[cfg(not(feature = "gui"))]
+fn confirm_update() -> Result<bool> {
+    println!("Updates are not installed automatically, since this build of package-assistant can't ask for confirmation.");
+    Ok(false)
+}
+
+#[cfg(feature = "gui")]
+fn gui(confirm_update: bool) -> Result<()> {
+    if confirm_update {
+        // The exit code tells the service whether the update was accepted
+        std::process::exit(if gui::confirm_update() { 0 } else { 1 })
+    }
+
     gui::start_app();
     Ok(())
 }
@@ -168,28 +1295,84 @@ fn gui() -> Result<()> {
 fn perform_test() -> Result<()> {
     let config = Config::fetch()?;
     let pkg_manager = package::get_package_manager(&config.package)?;
-    let ref changelog_query = ChangelogQuery { name: None };
+    let changelog_query = ChangelogQuery::default();
 
-    let updates = pkg_manager.check_update()?;
-    if updates.is_empty() {
-        println!("No updates available.");
-    } else {
-        println!("Available updates:");
-        for update in updates {
-            println!("{}", update);
-        }
-    }
+    let updates = pkg_manager.check_update(true)?;
+    print_updates(&updates, false);
 
-    pkg_manager.download_update(false)?;
-    let changelogs = pkg_manager.get_cached_changelogs(changelog_query)?;
+    pkg_manager.download_update(None, false, None)?;
+    let changelogs = pkg_manager.get_cached_changelogs(&changelog_query)?;
     println!("Changelog:\n{}", changelogs);
 
-    pkg_manager.do_update(false, false)?;
+    // Running the update for real would modify the system, so it's only previewed
+    match pkg_manager.preview_update(&UpdateOptions::default(), false, false) {
+        Ok(preview) => println!("Update (simulated):\n{}", preview.transaction.trim_end()),
+        Err(package::Error::Unsupported(_)) => println!("Update skipped, since this package manager can't simulate it."),
+        Err(err) => return Err(err.into())
+    }
 
-    println!("Test succeeded!");
+    println!("Test succeeded! The update step was simulated, so nothing was installed.");
     Ok(())
 }
 
+fn print_updates(updates: &UpdateSummary, group_by_category: bool) {
+    println!("{}", UpdateList { updates, group_by_category, limit: None }.render_text());
+}
+
+fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Formats a Unix timestamp relative to the current time, e.g. "5 minutes ago"
+fn format_time_ago(timestamp: u64) -> String {
+    let seconds = get_timestamp().saturating_sub(timestamp);
+    match seconds {
+        0..60 => String::from("just now"),
+        60..3600 => format!("{} minutes ago", seconds / 60),
+        3600..86400 => format!("{} hours ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400)
+    }
+}
+
+/// Parses a duration made up of a number and a unit, which is one of `s`, `m`, `h`, `d` or `w`, e.g. `30d`
+fn parse_duration(value: &str) -> std::result::Result<std::time::Duration, String> {
+    let unit_start = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number = number.parse::<u64>().map_err(|_| format!("'{}' must start with a number", value))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("'{}' must end with one of the units s, m, h, d or w", value))
+    };
+
+    Ok(std::time::Duration::from_secs(number.saturating_mul(unit_secs)))
+}
+
+/// Formats a number of bytes with a binary unit, e.g. `1.5 MiB`
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes)
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if size < 1024.0 {
+            break
+        }
+        size /= 1024.0;
+        unit = next_unit;
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
 fn handle_storage_result<T>(config_result: std::result::Result<T, storage::Error>) -> Result<Option<T>> {
     let result = match config_result {
         Err(storage::Error::FileAlreadyExists) => Ok(None),
@@ -198,4 +1381,254 @@ fn handle_storage_result<T>(config_result: std::result::Result<T, storage::Error
     };
 
     Ok(result?)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn parse_duration_accepts_every_unit() {
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(10 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_duration("1w"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_missing_number_or_unit() {
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("30y").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn canonical_types_come_from_package_and_storage() {
+        // Guards against a second copy of these types drifting from the ones the commands use
+        let item = PackageUpdateItem {
+            name: String::from("vim"),
+            old_version: None,
+            new_version: Some(String::from("9.1")),
+            category: None,
+            arch: None
+        };
+        let query = package::ChangelogQuery { name: Some(item.name.clone()), ..Default::default() };
+        let config: storage::Config = Config::default();
+
+        assert_eq!(query.name.as_deref(), Some("vim"));
+        assert_eq!(config.config_version, storage::CURRENT_CONFIG_VERSION);
+    }
+
+    #[test]
+    fn since_last_update_uses_recorded_timestamp() {
+        let data = Data { update_timestamp: 1704110400, ..Default::default() };
+        assert_eq!(get_last_update_timestamp(&data), Some(1704110400));
+        assert_eq!(get_last_update_timestamp(&Data::default()), None);
+    }
+
+    #[test]
+    fn status_without_updates_has_no_last_update() {
+        let report = StatusReport::new(&Data::default(), 2, 1704110400);
+        assert_eq!((report.last_update_timestamp, report.last_update_iso, report.seconds_since), (0, None, None));
+
+        let data = Data { update_timestamp: 1704110400, ..Default::default() };
+        let report = StatusReport::new(&data, 2, 1704114000);
+        assert_eq!(report.last_update_iso.as_deref(), Some("2024-01-01T12:00:00+00:00"));
+        assert_eq!(report.seconds_since, Some(3600));
+    }
+
+    #[test]
+    fn status_and_versions_render_json() {
+        let status = StatusReport { last_update_timestamp: 0, last_update_iso: None, seconds_since: None, pending_updates: 3 };
+        let json = serde_json::from_str::<serde_json::Value>(&status.render(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "last_update_timestamp": 0,
+            "last_update_iso": null,
+            "seconds_since": null,
+            "pending_updates": 3
+        }));
+
+        let versions = VersionReport {
+            package_assistant: "0.1.0",
+            package_manager: Some("zypper"),
+            tools: BTreeMap::from([("rpm", Some(String::from("4.20.0"))), ("zypper", None)])
+        };
+        let json = serde_json::from_str::<serde_json::Value>(&versions.render(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["package_manager"], "zypper");
+        assert_eq!(json["tools"], serde_json::json!({ "rpm": "4.20.0", "zypper": null }));
+    }
+
+    #[test]
+    fn check_update_output_is_written_to_file() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-output-test-{}", std::process::id()));
+        let path = dir.join("reports").join("updates.json");
+        let updates = UpdateSummary::new(vec![PackageUpdateItem {
+            name: String::from("vim"),
+            old_version: Some(String::from("9.0")),
+            new_version: Some(String::from("9.1")),
+            category: None,
+            arch: None
+        }]);
+        let output = UpdateList { updates: &updates, group_by_category: true, limit: None }.render(OutputFormat::Json).unwrap();
+
+        let result = write_output_file(&path, &output);
+        let contents = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        result.unwrap();
+        let json = serde_json::from_str::<serde_json::Value>(&contents.unwrap()).unwrap();
+        assert_eq!(json["items"][0]["new_version"], "9.1");
+        assert_eq!(json["total"], 1);
+    }
+
+    #[test]
+    fn init_check_creates_no_files() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-init-check-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let provided_path = dir.join("provided.toml");
+        let mut config = Config::default();
+        config.package.package_manager = Some(storage::PackageManagerType::Zypper);
+        config.package.download_command = String::from("zypper --non-interactive update --download-only");
+        config.package.update_command = String::from("zypper update");
+        config.package.noconfirm_update_command = String::from("zypper --non-interactive update");
+        std::fs::write(&provided_path, config.to_toml_str().unwrap()).unwrap();
+        let invalid_path = dir.join("invalid.toml");
+        std::fs::write(&invalid_path, Config::default().to_toml_str().unwrap()).unwrap();
+        let config_path = dir.join("config").join("settings.toml");
+        let data_path = dir.join("data").join("data.toml");
+
+        let valid = check_init_paths(Some(provided_path.clone()), &config_path, &data_path);
+        let invalid = check_init_paths(Some(invalid_path.clone()), &config_path, &data_path);
+        let mut entries = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<PathBuf>>();
+        entries.sort();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(valid.is_ok());
+        assert!(matches!(invalid, Err(Error::StorageError(storage::Error::InvalidConfig(_)))));
+        assert_eq!(entries, [invalid_path, provided_path]);
+    }
+
+    #[test]
+    fn init_print_round_trips_provided_and_default_config() {
+        let path = std::env::temp_dir().join(format!("package-assistant-init-print-test-{}.toml", std::process::id()));
+        std::fs::write(&path, "\
+[service]
+enable_service = true
+update_check_frequency = 90
+download_in_background = false
+update_on_reboot = false
+
+[package]
+package_manager = \"dnf\"
+download_command = \"\"
+update_command = \"dnf upgrade --refresh\"
+noconfirm_update_command = \"\"
+").unwrap();
+
+        let printed = get_init_config(Some(path.clone())).and_then(|config| Ok(config.to_toml_str()?));
+        let _ = std::fs::remove_file(&path);
+
+        let printed = printed.unwrap();
+        let config = Config::from_toml_str(&printed).unwrap();
+        assert_eq!(config.config_version, storage::CURRENT_CONFIG_VERSION);
+        assert_eq!(config.service.update_check_frequency, 90);
+        assert_eq!(config.package.update_command, "dnf upgrade --refresh");
+        assert!(!config.package.download_command.is_empty());
+        assert_eq!(config.to_toml_str().unwrap(), printed);
+
+        let printed = get_init_config(None).unwrap().to_toml_str().unwrap();
+        assert_eq!(Config::from_toml_str(&printed).unwrap().to_toml_str().unwrap(), printed);
+    }
+
+    #[test]
+    fn arch_filter_keeps_noarch_and_unknown_arch() {
+        let item = |name: &str, arch: Option<&str>| PackageUpdateItem {
+            name: String::from(name),
+            old_version: None,
+            new_version: None,
+            category: None,
+            arch: arch.map(String::from)
+        };
+        let items = vec![item("glibc", Some("x86_64")), item("glibc", Some("i686")), item("tzdata", Some("noarch")), item("vim", None)];
+
+        let filtered = filter_updates_by_arch(items, "x86_64");
+        let arches = filtered.iter().map(|item| item.arch.as_deref()).collect::<Vec<_>>();
+        assert_eq!(arches, [Some("x86_64"), Some("noarch"), None]);
+    }
+
+    #[test]
+    fn pending_updates_are_filtered_like_the_update() {
+        let item = |name: &str, category: Option<&str>| PackageUpdateItem {
+            name: String::from(name),
+            old_version: None,
+            new_version: None,
+            category: category.map(String::from),
+            arch: None
+        };
+        let items = || vec![item("kernel-default", Some("security")), item("glibc", Some("security")), item("vim", None)];
+        let names = |options: &UpdateOptions, ignored: &[String], only_security| {
+            filter_pending_updates(items(), ignored, options, only_security).into_iter()
+                .map(|item| item.name)
+                .collect::<Vec<String>>()
+        };
+
+        let all = UpdateOptions::default();
+        assert_eq!(names(&all, &[], false), ["kernel-default", "glibc", "vim"]);
+        assert_eq!(names(&all, &[], true), ["kernel-default", "glibc"]);
+        assert_eq!(names(&all, &[String::from("glibc")], false), ["kernel-default", "vim"]);
+
+        let excluded = UpdateOptions { exclude: vec![String::from("kernel*")], ..Default::default() };
+        assert_eq!(names(&excluded, &[], true), ["glibc"]);
+
+        let chosen = UpdateOptions { packages: vec![String::from("vim")], ..Default::default() };
+        assert_eq!(names(&chosen, &[], false), ["vim"]);
+    }
+
+    #[test]
+    fn refresh_is_due_once_max_age_has_passed() {
+        let last_refresh = 1704110400;
+        assert!(!is_refresh_due(last_refresh, 60, last_refresh + 59 * 60));
+        assert!(is_refresh_due(last_refresh, 60, last_refresh + 60 * 60));
+        assert!(is_refresh_due(0, 60, last_refresh));
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| String::from(*name)).collect()
+    }
+
+    #[test]
+    fn select_packages_includes_accepted_packages() {
+        let mut input = std::io::Cursor::new("y\nn\nyes\n");
+        let selected = select_packages(&names(&["vim", "git", "curl"]), &mut input).unwrap();
+        assert_eq!(selected, Some(names(&["vim", "curl"])));
+    }
+
+    #[test]
+    fn select_packages_asks_again_after_invalid_answer() {
+        let mut input = std::io::Cursor::new("maybe\nY\n");
+        let selected = select_packages(&names(&["vim"]), &mut input).unwrap();
+        assert_eq!(selected, Some(names(&["vim"])));
+    }
+
+    #[test]
+    fn select_packages_quits_on_q_or_end_of_input() {
+        let mut input = std::io::Cursor::new("y\nq\n");
+        assert_eq!(select_packages(&names(&["vim", "git", "curl"]), &mut input).unwrap(), None);
+
+        let mut input = std::io::Cursor::new("y\n");
+        assert_eq!(select_packages(&names(&["vim", "git"]), &mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn format_bytes_picks_binary_unit() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
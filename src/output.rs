@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::package::{self, ChangelogSummaryItem, PackageChangelogResult, PackageUpdateItem, RepoInfo, UpdateSummary};
+use crate::storage::PackageConfig;
+use crate::{Error, Result};
+
+pub const ANSI_RED: &str = "\x1b[1;31m";
+const ANSI_GREEN: &str = "\x1b[1;32m";
+const ANSI_BOLD: &str = "\x1b[1m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+static PRETTY_JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Markdown,
+    Porcelain,
+    Atom
+}
+
+impl OutputFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Markdown => "markdown",
+            OutputFormat::Porcelain => "porcelain",
+            OutputFormat::Atom => "atom"
+        }
+    }
+}
+
+/// The output of a command, which can be rendered in any of the formats it implements. Every command supports the
+/// text format, while the others return `Error::UnsupportedFormat` unless they're implemented.
+pub trait Render {
+    fn render(&self, format: OutputFormat) -> Result<String> {
+        match format {
+            OutputFormat::Text => Ok(self.render_text()),
+            OutputFormat::Json => self.render_json(),
+            OutputFormat::Markdown => self.render_markdown(),
+            OutputFormat::Porcelain => self.render_porcelain(),
+            OutputFormat::Atom => self.render_atom()
+        }
+    }
+
+    fn render_text(&self) -> String;
+
+    fn render_json(&self) -> Result<String> {
+        Err(Error::UnsupportedFormat(OutputFormat::Json))
+    }
+
+    fn render_markdown(&self) -> Result<String> {
+        Err(Error::UnsupportedFormat(OutputFormat::Markdown))
+    }
+
+    /// Stable output for scripts, which doesn't change between versions
+    fn render_porcelain(&self) -> Result<String> {
+        Err(Error::UnsupportedFormat(OutputFormat::Porcelain))
+    }
+
+    fn render_atom(&self) -> Result<String> {
+        Err(Error::UnsupportedFormat(OutputFormat::Atom))
+    }
+}
+
+/// The available updates as listed by `check-update`
+pub struct UpdateList<'a> {
+    pub updates: &'a UpdateSummary,
+    pub group_by_category: bool,
+    /// Maximum number of updates listed in the text format, which still reports the total
+    pub limit: Option<usize>
+}
+
+impl Render for UpdateList<'_> {
+    fn render_text(&self) -> String {
+        if self.updates.total == 0 {
+            return String::from("No updates available.")
+        }
+
+        let mut lines = Vec::new();
+        let mut remaining = self.limit.unwrap_or(usize::MAX);
+        if self.group_by_category {
+            for (category, items) in group_updates_by_category(&self.updates.items) {
+                if remaining == 0 {
+                    break
+                }
+
+                if !lines.is_empty() {
+                    lines.push(String::new());
+                }
+
+                let heading = format!("{} updates:", category);
+                let color = if category == "security" { ANSI_RED } else { ANSI_BOLD };
+                lines.push(colorize(&heading, color));
+                lines.extend(items.iter().take(remaining).copied().map(format_update_item));
+                remaining = remaining.saturating_sub(items.len());
+            }
+        } else {
+            lines.push(String::from("Available updates:"));
+            lines.extend(self.updates.items.iter().take(remaining).map(format_update_item));
+        }
+
+        let hidden_count = self.limit.map_or(0, |limit| self.updates.items.len().saturating_sub(limit));
+        if hidden_count > 0 {
+            lines.push(format!("… and {} more.", hidden_count));
+        }
+
+        lines.push(format!("\n{} updates available, {} of them security updates.", self.updates.total, self.updates.security_count));
+        lines.join("\n")
+    }
+
+    fn render_json(&self) -> Result<String> {
+        to_json(self.updates)
+    }
+
+    fn render_markdown(&self) -> Result<String> {
+        let mut lines = vec![
+            String::from("| Package | Current version | New version | Category |"),
+            String::from("| --- | --- | --- | --- |")
+        ];
+        lines.extend(self.updates.items.iter().map(|update| format!("| {} | {} | {} | {} |", update.name,
+            update.old_version.as_deref().unwrap_or("-"),
+            update.new_version.as_deref().unwrap_or("-"),
+            update.category.as_deref().unwrap_or("-"))));
+
+        Ok(lines.join("\n"))
+    }
+
+    /// One update per line as tab-separated name, old version and new version, with "-" for unknown versions, in a
+    /// format that won't change between releases
+    fn render_porcelain(&self) -> Result<String> {
+        Ok(self.updates.items.iter()
+            .map(|update| format!("{}\t{}\t{}", update.name,
+                update.old_version.as_deref().unwrap_or("-"),
+                update.new_version.as_deref().unwrap_or("-")))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+
+/// The changelogs listed by `changelog`
+pub struct ChangelogList<'a> {
+    pub config: &'a PackageConfig,
+    pub results: &'a [PackageChangelogResult],
+    /// Makes every changelog entry a feed entry in the Atom format, rather than every package
+    pub feed_per_entry: bool
+}
+
+impl Render for ChangelogList<'_> {
+    fn render_text(&self) -> String {
+        package::format_changelog_results(self.config, self.results)
+    }
+
+    fn render_json(&self) -> Result<String> {
+        to_json(&package::get_changelog_entries_by_name(self.results))
+    }
+
+    fn render_markdown(&self) -> Result<String> {
+        Ok(package::format_changelog_results_markdown(self.config, self.results))
+    }
+
+    fn render_atom(&self) -> Result<String> {
+        Ok(package::format_changelog_results_atom(self.config, self.results, self.feed_per_entry))
+    }
+}
+
+/// The number of changelog entries per package listed by `changelog --summary`
+pub struct ChangelogSummary<'a> {
+    pub config: &'a PackageConfig,
+    pub summary: &'a [ChangelogSummaryItem]
+}
+
+impl Render for ChangelogSummary<'_> {
+    fn render_text(&self) -> String {
+        package::format_changelog_summary(self.config, self.summary)
+    }
+
+    fn render_json(&self) -> Result<String> {
+        to_json(self.summary)
+    }
+}
+
+/// The repositories listed by `list-repos`
+pub struct RepoList<'a> {
+    pub repos: &'a [RepoInfo]
+}
+
+impl Render for RepoList<'_> {
+    fn render_text(&self) -> String {
+        if self.repos.is_empty() {
+            return String::from("No repositories configured.")
+        }
+
+        let mut lines = vec![String::from("Repositories:")];
+        lines.extend(self.repos.iter().map(|repo| repo.to_string()));
+        lines.join("\n")
+    }
+
+    fn render_json(&self) -> Result<String> {
+        to_json(self.repos)
+    }
+}
+
+/// Groups updates by category, with security updates first and updates without a category under "other" last
+fn group_updates_by_category(updates: &[PackageUpdateItem]) -> Vec<(&str, Vec<&PackageUpdateItem>)> {
+    let mut groups: BTreeMap<(u8, &str), Vec<&PackageUpdateItem>> = BTreeMap::new();
+    for update in updates {
+        let key = match update.category.as_deref() {
+            Some("security") => (0, "security"),
+            Some(category) => (1, category),
+            None => (2, "other")
+        };
+        groups.entry(key).or_default().push(update);
+    }
+
+    groups.into_iter()
+        .map(|((_, category), items)| (category, items))
+        .collect()
+}
+
+/// Formats an update like its `Display` implementation, but with the changed part of the new version highlighted
+/// when colors are enabled
+fn format_update_item(update: &PackageUpdateItem) -> String {
+    match (&update.old_version, &update.new_version) {
+        (Some(old_version), Some(new_version)) if is_color_enabled() => {
+            let (prefix_len, suffix_len) = get_version_affix_lengths(old_version, new_version);
+            let changed = &new_version[prefix_len..new_version.len() - suffix_len];
+            format!("{} ({}) -> ({}{}{})", update.name, old_version, &new_version[..prefix_len],
+                colorize(changed, ANSI_GREEN), &new_version[new_version.len() - suffix_len..])
+        },
+        _ => update.to_string()
+    }
+}
+
+/// Gets the byte lengths of the common prefix and suffix of two versions, which don't overlap and don't end in the
+/// middle of a number, so that e.g. `1.2.19 -> 1.2.29` highlights `29` rather than just `2`
+fn get_version_affix_lengths(old_version: &str, new_version: &str) -> (usize, usize) {
+    let max_len = old_version.len().min(new_version.len());
+    let mut prefix_len = old_version.char_indices()
+        .zip(new_version.chars())
+        .find(|((_, old_char), new_char)| old_char != new_char)
+        .map_or(max_len, |((i, _), _)| i);
+    let mut suffix_len = old_version.char_indices().rev()
+        .zip(new_version.chars().rev())
+        .find(|((_, old_char), new_char)| old_char != new_char)
+        .map_or(max_len, |((i, old_char), _)| old_version.len() - i - old_char.len_utf8())
+        .min(max_len - prefix_len);
+
+    let is_digit_at = |index: Option<usize>| index.and_then(|index| new_version.as_bytes().get(index)).is_some_and(u8::is_ascii_digit);
+    while prefix_len > 0 && is_digit_at(Some(prefix_len - 1)) && is_digit_at(Some(prefix_len)) {
+        prefix_len -= 1;
+    }
+    while suffix_len > 0 && is_digit_at(Some(new_version.len() - suffix_len))
+        && is_digit_at((new_version.len() - suffix_len).checked_sub(1)) {
+        suffix_len -= 1;
+    }
+
+    (prefix_len, suffix_len)
+}
+
+fn is_color_enabled() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Wraps `text` in an ANSI color code if stdout is a terminal and NO_COLOR isn't set
+pub fn colorize(text: &str, color: &str) -> String {
+    if is_color_enabled() {
+        format!("{}{}{}", color, text, ANSI_RESET)
+    } else {
+        String::from(text)
+    }
+}
+
+/// Makes the JSON format indented over several lines for the rest of the process, instead of a single line
+pub fn enable_pretty_json() {
+    PRETTY_JSON.store(true, Ordering::SeqCst);
+}
+
+/// Serializes `value` for the JSON format, which is compact unless `enable_pretty_json` was called
+pub fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    serialize_json(value, PRETTY_JSON.load(Ordering::SeqCst))
+}
+
+fn serialize_json<T: Serialize + ?Sized>(value: &T, pretty: bool) -> Result<String> {
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+
+    Ok(json?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::package::{ChangelogEntry, PackageUpdateItem, UpdateSummary};
+    use crate::storage::Config;
+
+    use super::*;
+
+    fn update(name: &str, category: Option<&str>) -> PackageUpdateItem {
+        PackageUpdateItem {
+            name: String::from(name),
+            old_version: None,
+            new_version: None,
+            category: category.map(String::from),
+            arch: None
+        }
+    }
+
+    #[test]
+    fn version_affixes_keep_epoch_and_stop_before_changed_release() {
+        assert_eq!(get_version_affix_lengths("1:2.3-4", "1:2.4-1"), (4, 0));
+    }
+
+    #[test]
+    fn version_affixes_find_unchanged_suffix() {
+        assert_eq!(get_version_affix_lengths("2.3.0-1", "2.4.0-1"), (2, 4));
+    }
+
+    #[test]
+    fn version_affixes_dont_split_numbers() {
+        assert_eq!(get_version_affix_lengths("1.2.19", "1.2.29"), (4, 0));
+        assert_eq!(get_version_affix_lengths("1.9-1", "1.10-1"), (2, 2));
+    }
+
+    #[test]
+    fn version_affixes_of_equal_versions_dont_overlap() {
+        assert_eq!(get_version_affix_lengths("1.0", "1.0"), (3, 0));
+    }
+
+    #[test]
+    fn updates_are_grouped_with_security_first_and_other_last() {
+        let updates = [
+            update("vim", None),
+            update("git", Some("recommended")),
+            update("openssl", Some("security")),
+            update("curl", Some("bugfix")),
+            update("glibc", Some("security"))
+        ];
+
+        let groups = group_updates_by_category(&updates).into_iter()
+            .map(|(category, items)| (category, items.iter().map(|item| item.name.as_str()).collect::<Vec<&str>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, [
+            ("security", vec!["openssl", "glibc"]),
+            ("bugfix", vec!["curl"]),
+            ("recommended", vec!["git"]),
+            ("other", vec!["vim"])
+        ]);
+    }
+
+    #[test]
+    fn porcelain_lists_tab_separated_versions() {
+        let mut vim = update("vim", Some("security"));
+        vim.old_version = Some(String::from("9.0.2-1.1"));
+        vim.new_version = Some(String::from("9.1.0-1.1"));
+        let mut git = update("git", None);
+        git.new_version = Some(String::from("2.46.0-1.1"));
+        let updates = UpdateSummary::new(vec![vim, git]);
+
+        let output = UpdateList { updates: &updates, group_by_category: true, limit: Some(1) }.render(OutputFormat::Porcelain);
+        assert_eq!(output.unwrap(), "git\t-\t2.46.0-1.1\nvim\t9.0.2-1.1\t9.1.0-1.1");
+    }
+
+    #[test]
+    fn limit_lists_first_updates_and_counts_the_rest() {
+        let updates = UpdateSummary::new(vec![
+            update("vim", None),
+            update("openssl", Some("security")),
+            update("curl", Some("bugfix")),
+            update("glibc", Some("security"))
+        ]);
+
+        // Headings may be colored when the tests run in a terminal, so only the package lines are compared
+        let render = |group_by_category, limit| {
+            let output = UpdateList { updates: &updates, group_by_category, limit }.render_text();
+            assert!(output.ends_with("4 updates available, 2 of them security updates."), "{}", output);
+            output.lines()
+                .filter(|line| !line.is_empty() && !line.contains("updates"))
+                .map(String::from)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(render(false, Some(2)), ["curl", "glibc", "… and 2 more."]);
+        assert_eq!(render(true, Some(3)), ["glibc", "openssl", "curl", "… and 1 more."]);
+        assert_eq!(render(false, Some(10)), ["curl", "glibc", "openssl", "vim"]);
+        assert_eq!(render(true, Some(10)), ["glibc", "openssl", "curl", "vim"]);
+    }
+
+    #[test]
+    fn json_is_compact_unless_pretty() {
+        // The pretty setting is global, so this serializes directly instead of enabling it for the other tests
+        let updates = UpdateSummary::new(vec![update("vim", Some("security"))]);
+
+        let compact = serialize_json(&updates, false).unwrap();
+        let pretty = serialize_json(&updates, true).unwrap();
+        assert!(!compact.contains('\n'));
+        assert!(pretty.lines().count() > 1);
+        assert!(pretty.contains("\n  \"total\": 1"), "{}", pretty);
+        assert_eq!(serde_json::from_str::<Value>(&compact).unwrap(), serde_json::from_str::<Value>(&pretty).unwrap());
+    }
+
+    fn render_json_value(output: &dyn Render) -> Value {
+        serde_json::from_str(&output.render(OutputFormat::Json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn every_command_output_renders_json() {
+        let config = Config::default().package;
+
+        let updates = UpdateSummary::new(vec![update("vim", Some("security"))]);
+        let update_list = UpdateList { updates: &updates, group_by_category: false, limit: None };
+        let json = render_json_value(&update_list);
+        assert_eq!(json["total"], 1);
+        assert_eq!(json["security_count"], 1);
+        assert_eq!(json["items"][0]["name"], "vim");
+
+        let results = [PackageChangelogResult {
+            name: String::from("vim"),
+            changelogs: vec![ChangelogEntry { timestamp: 1704110400, author: None, description: String::from("- Update to 9.1") }]
+        }];
+        let changelog_list = ChangelogList { config: &config, results: &results, feed_per_entry: false };
+        assert_eq!(render_json_value(&changelog_list), json!({
+            "vim": [{ "timestamp": 1704110400, "author": null, "description": "- Update to 9.1" }]
+        }));
+
+        let summary = package::summarize_changelog_results(&results);
+        let changelog_summary = ChangelogSummary { config: &config, summary: &summary };
+        assert_eq!(render_json_value(&changelog_summary), json!([{ "name": "vim", "count": 1, "newest_timestamp": 1704110400 }]));
+
+        let repos = [RepoInfo { id: String::from("oss"), name: String::from("Main Repository"), enabled: true, priority: Some(99) }];
+        assert_eq!(render_json_value(&RepoList { repos: &repos }), json!([
+            { "id": "oss", "name": "Main Repository", "enabled": true, "priority": 99 }
+        ]));
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::{ChangelogQuery, Error, PackageChangelogResult, PackageManager, PackageUpdateItem};
+use super::error::Result;
+
+/// The bundle format version. Increment this whenever the format changes in a way that older
+/// versions of package-assistant can't read.
+pub const BUNDLE_VERSION: u32 = 2;
+
+/// A snapshot of the available updates and their cached changelogs, so that they can be
+/// reported on a machine other than the one they were collected on.
+#[derive(Deserialize, Serialize)]
+pub struct UpdateBundle {
+    pub version: u32,
+    pub created_timestamp: u64,
+    pub updates: Vec<PackageUpdateItem>,
+    pub changelogs: Vec<PackageChangelogResult>
+}
+
+impl UpdateBundle {
+    /// Collects the available updates and the changelogs of all cached packages
+    pub fn create(pkg_manager: &dyn PackageManager) -> Result<Self> {
+        let updates = pkg_manager.check_update(true)?.items;
+        let changelogs = match pkg_manager.get_cached_changelog_results(&ChangelogQuery::default()) {
+            Err(Error::NoChangelogsInDirectory | Error::NoPackagesInDirectory) => Vec::new(),
+            result => result?
+        };
+        let created_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        Ok(Self { version: BUNDLE_VERSION, created_timestamp, updates, changelogs })
+    }
+
+    /// Reads a bundle from a JSON file. Returns `Error::UnsupportedBundleVersion` if the bundle
+    /// was written by a newer version of package-assistant.
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let bundle = serde_json::from_str::<Self>(&contents)?;
+
+        if bundle.version > BUNDLE_VERSION {
+            Err(Error::UnsupportedBundleVersion(bundle.version))
+        } else {
+            Ok(bundle)
+        }
+    }
+
+    /// Writes the bundle to a JSON file
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string(self)?;
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+use super::PackageChangelogResult;
+use super::error::Result;
+
+const CHANGELOG_CACHE_FILE_NAME: &str = "changelogs.json";
+
+/// Parsed changelogs of package files, so that packages that haven't changed since they were last scanned don't
+/// need to be opened again. Entries are invalidated when a file's modification time or size changes.
+#[derive(Default, Deserialize, Serialize)]
+pub struct ChangelogCache {
+    packages: HashMap<PathBuf, CachedChangelogs>,
+    #[serde(skip)]
+    changed: bool
+}
+
+#[derive(Deserialize, Serialize)]
+struct CachedChangelogs {
+    modified_nanos: u64,
+    size: u64,
+    result: PackageChangelogResult
+}
+
+impl ChangelogCache {
+    /// Reads the cache from the cache directory. Since it can always be rebuilt, an empty cache is returned if
+    /// it can't be read.
+    pub fn load() -> Self {
+        get_cache_file_path()
+            .and_then(|path| Self::load_from(&path))
+            .unwrap_or_default()
+    }
+
+    fn load_from(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    /// Gets the changelogs of the package file at `path`, if it hasn't changed since they were cached
+    pub fn get(&self, path: &Path) -> Option<PackageChangelogResult> {
+        let (modified_nanos, size) = get_file_key(path)?;
+
+        self.packages.get(path)
+            .filter(|cached| cached.modified_nanos == modified_nanos && cached.size == size)
+            .map(|cached| cached.result.clone())
+    }
+
+    pub fn insert(&mut self, path: &Path, result: PackageChangelogResult) {
+        if let Some((modified_nanos, size)) = get_file_key(path) {
+            self.packages.insert(path.to_owned(), CachedChangelogs { modified_nanos, size, result });
+            self.changed = true;
+        }
+    }
+
+    /// Writes the cache to the cache directory if anything was added, leaving out packages that no longer exist
+    pub fn save(self) -> Result<()> {
+        match get_cache_file_path().filter(|_| self.changed) {
+            Some(path) => self.save_to(&path),
+            None => Ok(())
+        }
+    }
+
+    /// Writes the cache to `path`, along with the entries that other processes saved since it was loaded. The file is
+    /// written while holding a lock and then replaced at once, so that concurrent scans neither lose each other's
+    /// entries nor leave a partially written file behind.
+    fn save_to(mut self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let lock_file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension("lock"))?;
+        lock_file.lock()?;
+
+        if let Some(saved) = Self::load_from(path) {
+            let packages = std::mem::replace(&mut self.packages, saved.packages);
+            self.packages.extend(packages);
+        }
+        self.packages.retain(|package_path, _| package_path.exists());
+
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_string(&self)?)?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+}
+
+fn get_cache_file_path() -> Option<PathBuf> {
+    let mut path = storage::get_cache_dir_path().ok()?;
+    path.push(CHANGELOG_CACHE_FILE_NAME);
+    Some(path)
+}
+
+/// Gets the modification time and size that identify the current contents of a file
+fn get_file_key(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    Some((u64::try_from(modified.as_nanos()).ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::package::ChangelogEntry;
+
+    use super::*;
+
+    fn result(name: &str) -> PackageChangelogResult {
+        PackageChangelogResult { name: String::from(name), changelogs: Vec::new() }
+    }
+
+    #[test]
+    fn saved_entries_are_found_until_the_package_changes() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-cache-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let package_path = dir.join("vim.rpm");
+        let cache_path = dir.join(CHANGELOG_CACHE_FILE_NAME);
+        fs::write(&package_path, "package").unwrap();
+
+        let mut cache = ChangelogCache::default();
+        cache.insert(&package_path, result("vim"));
+        cache.save_to(&cache_path).unwrap();
+        let hit = ChangelogCache::load_from(&cache_path).unwrap().get(&package_path).map(|result| result.name);
+
+        fs::write(&package_path, "changed package").unwrap();
+        let miss = ChangelogCache::load_from(&cache_path).unwrap().get(&package_path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(hit.as_deref(), Some("vim"));
+        assert!(miss.is_none());
+    }
+
+    #[test]
+    fn saving_keeps_entries_saved_by_another_scan() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-cache-merge-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache_path = dir.join(CHANGELOG_CACHE_FILE_NAME);
+        let (vim_path, git_path) = (dir.join("vim.rpm"), dir.join("git.rpm"));
+        fs::write(&vim_path, "package").unwrap();
+        fs::write(&git_path, "package").unwrap();
+
+        let (mut first, mut second) = (ChangelogCache::default(), ChangelogCache::default());
+        first.insert(&vim_path, result("vim"));
+        second.insert(&git_path, result("git"));
+        first.save_to(&cache_path).unwrap();
+        second.save_to(&cache_path).unwrap();
+        let cache = ChangelogCache::load_from(&cache_path).unwrap();
+        let names = [&vim_path, &git_path].map(|path| cache.get(path).map(|result| result.name));
+        let temp_file_exists = cache_path.with_extension("tmp").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names, [Some(String::from("vim")), Some(String::from("git"))]);
+        assert!(!temp_file_exists);
+    }
+
+    #[test]
+    fn index_format_round_trips_changelogs() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-cache-format-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache_path = dir.join(CHANGELOG_CACHE_FILE_NAME);
+        let package_path = dir.join("vim.rpm");
+        fs::write(&package_path, "package").unwrap();
+        let changelogs = vec![
+            ChangelogEntry {
+                timestamp: 1704888000,
+                author: Some(String::from("Jörg Doe <jorg@example.com> - 9.1-2")),
+                description: String::from("- Fix \"quoted\" paths\n  * and tabs\t")
+            },
+            ChangelogEntry { timestamp: 1704110400, author: None, description: String::new() }
+        ];
+
+        let mut cache = ChangelogCache::default();
+        cache.insert(&package_path, PackageChangelogResult { name: String::from("vim"), changelogs: changelogs.clone() });
+        cache.save_to(&cache_path).unwrap();
+        let loaded = ChangelogCache::load_from(&cache_path).unwrap().get(&package_path);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.name, "vim");
+        let fields = |changelogs: &[ChangelogEntry]| changelogs.iter()
+            .map(|changelog| (changelog.timestamp, changelog.author.clone(), changelog.description.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(fields(&loaded.changelogs), fields(&changelogs));
+    }
+}
@@ -1,15 +1,66 @@
+use std::collections::HashSet;
 use std::path::Path;
-use std::process::Command;
-
-use regex::Regex;
+use std::sync::OnceLock;
 
 use crate::storage::PackageConfig;
 
-use super::{utilities, ChangelogQuery, Error, PackageChangelogResult, PackageManager, PackageUpdateItem};
+use super::{rpm, utilities, ChangelogQuery, Error, PackageChangelogResult, PackageManager, PackageUpdateItem, PackageVerificationResult, RepoInfo, UpdateSummary};
 use super::error::Result;
 
 pub struct DnfManger<'a> {
-    pub config: &'a PackageConfig
+    pub config: &'a PackageConfig,
+    dnf5: OnceLock<bool>
+}
+
+pub fn create_manager(config: &PackageConfig) -> Box<dyn PackageManager + '_> {
+    Box::new(DnfManger { config, dnf5: OnceLock::new() })
+}
+
+impl DnfManger<'_> {
+    /// dnf as it's written in the commands that are run in a shell
+    fn dnf(&self) -> String {
+        utilities::get_shell_program(self.config, "dnf")
+    }
+
+    /// Whether dnf is dnf5, whose subcommands differ from dnf4. This is detected from `dnf --version`, unless
+    /// it's set in settings.
+    fn is_dnf5(&self) -> bool {
+        *self.dnf5.get_or_init(|| {
+            self.config.dnf5.unwrap_or_else(|| {
+                utilities::tool_version(utilities::get_program_path(self.config, "dnf"))
+                    .and_then(|version| version.split('.').next()?.parse::<u32>().ok())
+                    .is_some_and(|major_version| major_version >= 5)
+            })
+        })
+    }
+
+    /// Sets the category of each update from the type of its advisory, as listed by `dnf updateinfo`, or
+    /// `dnf advisory` in dnf5
+    fn set_categories(&self, items: &mut [PackageUpdateItem]) -> Result<()> {
+        let subcommand = if self.is_dnf5() { "advisory" } else { "updateinfo" };
+        let output = utilities::new_command(self.config, "dnf")
+            .args([subcommand, "list", "--updates"])
+            .output()?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::DnfError))?;
+
+        for line in stdout.lines() {
+            let columns = line.split_whitespace().collect::<Vec<&str>>();
+            let category = columns.iter().find_map(|column| parse_advisory_type(column));
+            let index = columns.iter().find_map(|column| {
+                let name = nevra_to_name_arch(column)?;
+                items.iter().position(|item| item.name == name)
+            });
+
+            if let (Some(category), Some(item)) = (category, index.and_then(|i| items.get_mut(i))) {
+                // A package can be part of several advisories, in which case security takes precedence
+                if item.category.as_deref() != Some("security") {
+                    item.category = Some(category.to_owned());
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> PackageManager for DnfManger<'a> {
@@ -17,23 +68,442 @@ impl<'a> PackageManager for DnfManger<'a> {
         self.config
     }
 
-    fn get_package_changelogs_result(&self, query: &ChangelogQuery, path: &Path) -> Result<PackageChangelogResult> {
-        utilities::get_rpm_changelogs_result(query, path)
+    fn package_extensions(&self) -> &[&str] {
+        &["rpm"]
     }
 
-    fn check_update(&self) -> Result<Vec<PackageUpdateItem>> {
-        let output = Command::new("dnf")
-            .arg("check-update")
-            .output()?;
+    fn read_package_changelogs(&self, path: &Path) -> Result<PackageChangelogResult> {
+        rpm::read_rpm_changelogs(self.config, path)
+    }
+
+    fn filter_package_changelogs(&self, query: &ChangelogQuery, result: PackageChangelogResult) -> Result<PackageChangelogResult> {
+        rpm::filter_rpm_changelogs(self.config, query, result)
+    }
+
+    fn verify_cache(&self) -> Result<Vec<PackageVerificationResult>> {
+        rpm::verify_rpm_cache(self.config)
+    }
+
+    fn installed_packages(&self) -> Result<HashSet<String>> {
+        rpm::get_installed_rpm_names(self.config)
+    }
+
+    fn get_installed_changelog_results(&self, query: &ChangelogQuery, name: &str) -> Result<Vec<PackageChangelogResult>> {
+        Ok(vec![rpm::get_installed_rpm_changelogs(self.config, query, name)?])
+    }
+
+    fn check_update(&self, refresh: bool) -> Result<UpdateSummary> {
+        let mut command = utilities::new_check_update_override(self.config).unwrap_or_else(|| {
+            let mut command = utilities::new_command(self.config, "dnf");
+            command.arg(if self.is_dnf5() { "check-upgrade" } else { "check-update" });
+            if !refresh {
+                command.arg("--cacheonly");
+            }
+            command
+        });
+        let output = command.output()?;
+
+        // check-update exits with 100 if there are updates, so its exit code alone doesn't tell whether it failed
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() && utilities::is_manager_locked(&stderr) {
+            return Err(Error::ManagerLocked(stderr.into_owned()))
+        }
+
         let cmd_result = utilities::process_cmd_output::<fn(String) -> Error>(output, None)?;
+        let mut items = parse_check_update_output(&cmd_result);
+
+        // Categories are only informational, so the updates are still returned if the advisories can't be listed
+        let _ = self.set_categories(&mut items);
+
+        Ok(UpdateSummary::new(items))
+    }
+
+    fn reboot_required(&self) -> Option<bool> {
+        let output = utilities::new_command(self.config, "dnf")
+            .args(["needs-restarting", "-r"])
+            .output()
+            .ok()?;
+
+        // dnf also exits with 1 if the needs-restarting plugin isn't installed, but then prints nothing to stdout
+        match output.status.code() {
+            Some(0) => Some(false),
+            Some(1) if !output.stdout.is_empty() => Some(true),
+            _ => None
+        }
+    }
+
+    fn download_package(&self, item: &PackageUpdateItem, path: &Path) -> Result<()> {
+        let command = format!("{} download --destdir {} {}", self.dnf(),
+            utilities::quote_shell_arg(&path.to_string_lossy()), utilities::quote_shell_arg(&item.name));
+        utilities::run_shell_command(self.config, &command, false, Some(Error::download_error))
+    }
+
+    fn get_download_dir_command(&self, command: &str, download_dir: &Path) -> Option<String> {
+        utilities::strip_shell_program(self.config, command, "dnf")?;
+
+        Some(format!("{} --downloaddir {}", command, utilities::quote_shell_arg(&download_dir.to_string_lossy())))
+    }
+
+    fn get_security_update_command(&self, interactive: bool) -> Option<String> {
+        if interactive {
+            Some(format!("{} upgrade --security", self.dnf()))
+        } else {
+            Some(format!("{} upgrade -y --security", self.dnf()))
+        }
+    }
+
+    fn get_package_update_command(&self, interactive: bool) -> Option<String> {
+        if interactive {
+            Some(format!("{} upgrade", self.dnf()))
+        } else {
+            Some(format!("{} upgrade -y", self.dnf()))
+        }
+    }
+
+    fn get_fail_on_conflict_command(&self, command: &str) -> Option<String> {
+        utilities::strip_shell_program(self.config, command, "dnf")?;
+
+        // Without --allowerasing, dnf already refuses to remove packages, but it silently skips the updates it can't
+        // install unless --best is given, which --include-optional may have added already
+        if command.split_whitespace().any(|arg| arg == "--best") {
+            Some(format!("{} --setopt=allow_vendor_change=False", command))
+        } else {
+            Some(format!("{} --best --setopt=allow_vendor_change=False", command))
+        }
+    }
+
+    fn get_include_optional_args(&self) -> Option<String> {
+        // Insists on the newest versions, and replaces packages that are obsoleted by others
+        Some(String::from("--best --setopt=obsoletes=True"))
+    }
+
+    fn get_preview_command(&self, interactive_command: &str, _noconfirm_command: &str) -> Option<String> {
+        Some(format!("{} --assumeno", interactive_command))
+    }
+
+    fn get_default_offline_update_command(&self) -> Option<String> {
+        Some(String::from(utilities::PACKAGEKIT_OFFLINE_UPDATE_COMMAND))
+    }
+
+    fn list_repositories(&self) -> Result<Vec<RepoInfo>> {
+        let output = utilities::new_command(self.config, "dnf")
+            .args(["repolist", "--all"])
+            .output()?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::DnfError))?;
+
+        Ok(parse_repolist_output(&stdout))
+    }
+
+    fn get_repository_enabled_command(&self, id: &str, enabled: bool) -> Option<String> {
+        if self.is_dnf5() {
+            Some(format!("{} config-manager setopt {}", self.dnf(), utilities::quote_shell_arg(&format!("{}.enabled={}", id, u8::from(enabled)))))
+        } else {
+            let flag = if enabled { "--set-enabled" } else { "--set-disabled" };
+            Some(format!("{} config-manager {} {}", self.dnf(), flag, utilities::quote_shell_arg(id)))
+        }
+    }
+
+    fn services_needing_restart(&self) -> Result<Vec<String>> {
+        let output = utilities::new_command(self.config, "dnf")
+            .args(["needs-restarting", "-s"])
+            .output()?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::DnfError))?;
+
+        Ok(utilities::parse_lines(&stdout))
+    }
+
+    fn get_exclude_args(&self, patterns: &[String]) -> Option<String> {
+        let args = patterns.iter()
+            .map(|pattern| utilities::quote_shell_arg(&format!("--exclude={}", pattern)))
+            .collect::<Vec<String>>();
 
-        let regex = Regex::new(r"(?m)^(\S+)\s+(\S+)\s+updates$")?;
-        let items = regex.captures_iter(&cmd_result).map(|c| {
-            let (_, [name, version]) = c.extract();
-            PackageUpdateItem { name: name.to_owned(), new_version: Some(version.to_owned()), old_version: None}
+        Some(args.join(" "))
+    }
+}
+
+/// Parses the output of `dnf check-update`, or `dnf check-upgrade` in dnf5, which share the same layout. Since
+/// the headers and messages may be localized, updates are identified by their structure instead: a `name.arch`
+/// column, a version column and a repository column. Names that are too long are wrapped onto their own line,
+/// with the remaining columns indented below.
+fn parse_check_update_output(output: &str) -> Vec<PackageUpdateItem> {
+    let mut items: Vec<PackageUpdateItem> = Vec::new();
+    let mut wrapped_name: Option<&str> = None;
+
+    for line in output.lines() {
+        let indented = line.starts_with(char::is_whitespace);
+        let mut columns = line.split_whitespace().collect::<Vec<&str>>();
+
+        match wrapped_name.take() {
+            Some(name) if indented => columns.insert(0, name),
+            // Indented lines that aren't a continuation list the installed packages being obsoleted
+            _ if indented => continue,
+            _ => ()
+        }
+
+        match columns[..] {
+            [name] => wrapped_name = Some(name),
+            // Packages that obsolete others are listed a second time
+            [name, version, _repo] if version.contains(|c: char| c.is_ascii_digit())
+                && !items.iter().any(|item| item.name == name) => {
+                let arch = name.rsplit_once('.').map(|(_, arch)| arch.to_owned());
+                items.push(PackageUpdateItem { name: name.to_owned(), new_version: Some(version.to_owned()), old_version: None, category: None, arch });
+            },
+            _ => ()
+        }
+    }
+
+    items
+}
+
+/// Parses the output of `dnf repolist --all`, which has columns for the repo id, name and status in both dnf4 and
+/// dnf5. Lines that don't end in a status, such as the header, are skipped. The priority isn't listed, so it's left
+/// unknown.
+fn parse_repolist_output(output: &str) -> Vec<RepoInfo> {
+    output.lines()
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<&str>>();
+            let (&status, columns) = columns.split_last()?;
+            let (&id, name_columns) = columns.split_first()?;
+            let enabled = match status {
+                "enabled" => true,
+                "disabled" => false,
+                _ => return None
+            };
+
+            Some(RepoInfo { id: id.to_owned(), name: name_columns.join(" "), enabled, priority: None })
         })
-        .collect::<Vec<PackageUpdateItem>>();
+        .collect()
+}
+
+/// Parses the advisory type column of `dnf updateinfo list`. Security advisories are listed by their
+/// severity, e.g. `Important/Sec.`, by dnf 4.
+fn parse_advisory_type(column: &str) -> Option<&'static str> {
+    match column {
+        "security" => Some("security"),
+        _ if column.ends_with("/Sec.") => Some("security"),
+        "bugfix" => Some("bugfix"),
+        "enhancement" => Some("enhancement"),
+        "newpackage" => Some("newpackage"),
+        _ => None
+    }
+}
+
+/// Converts a package NEVRA (`name-[epoch:]version-release.arch`) to the `name.arch` format used by `dnf check-update`
+fn nevra_to_name_arch(nevra: &str) -> Option<String> {
+    let (nevr, arch) = nevra.rsplit_once('.')?;
+    let (nev, _release) = nevr.rsplit_once('-')?;
+    let (name, _version) = nev.rsplit_once('-')?;
+
+    Some(format!("{}.{}", name, arch))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+
+    use crate::storage::{Config, PackageConfig};
+
+    use super::super::{Error, PackageManager, UpdateOptions};
+    use super::{parse_check_update_output, DnfManger};
+
+    fn test_config() -> PackageConfig {
+        let mut config = Config::default().package;
+        config.dnf5 = Some(false);
+        config
+    }
+
+    fn manager(config: &PackageConfig) -> DnfManger<'_> {
+        DnfManger { config, dnf5: OnceLock::new() }
+    }
+
+    #[test]
+    fn fail_on_conflict_adds_best_and_vendor_lock() {
+        let config = test_config();
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let command = manager(&config).build_update_command("dnf upgrade -y", &options, false).unwrap();
+        assert_eq!(command, "dnf upgrade -y --best --setopt=allow_vendor_change=False");
+    }
+
+    #[test]
+    fn fail_on_conflict_with_include_optional_adds_best_once() {
+        let config = test_config();
+        let options = UpdateOptions { fail_on_conflict: true, include_optional: true, ..Default::default() };
+        let command = manager(&config).build_update_command("dnf upgrade -y", &options, false).unwrap();
+        assert_eq!(command, "dnf upgrade -y --best --setopt=obsoletes=True --setopt=allow_vendor_change=False");
+        assert_eq!(command.matches("--best").count(), 1);
+    }
+
+    #[test]
+    fn fail_on_conflict_is_ignored_for_interactive_updates() {
+        let config = test_config();
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let command = manager(&config).build_update_command("dnf upgrade", &options, true).unwrap();
+        assert_eq!(command, "dnf upgrade");
+    }
+
+    #[test]
+    fn fail_on_conflict_is_unsupported_for_wrapped_commands() {
+        let config = test_config();
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let result = manager(&config).build_update_command("sudo /usr/local/bin/update.sh", &options, false);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn include_optional_adds_best_and_obsoletes() {
+        let config = test_config();
+        let options = UpdateOptions { include_optional: true, ..Default::default() };
+        let command = manager(&config).build_update_command("dnf upgrade", &options, true).unwrap();
+        assert_eq!(command, "dnf upgrade --best --setopt=obsoletes=True");
+    }
+
+    #[test]
+    fn repository_enabled_command_uses_set_enabled_with_dnf4() {
+        let config = test_config();
+        let manager = manager(&config);
+        assert_eq!(manager.get_repository_enabled_command("updates", true).as_deref(), Some("dnf config-manager --set-enabled 'updates'"));
+        assert_eq!(manager.get_repository_enabled_command("updates", false).as_deref(), Some("dnf config-manager --set-disabled 'updates'"));
+    }
+
+    #[test]
+    fn repository_enabled_command_uses_setopt_with_dnf5() {
+        let mut config = test_config();
+        config.dnf5 = Some(true);
+        let manager = manager(&config);
+        assert_eq!(manager.get_repository_enabled_command("updates", true).as_deref(), Some("dnf config-manager setopt 'updates.enabled=1'"));
+        assert_eq!(manager.get_repository_enabled_command("updates", false).as_deref(), Some("dnf config-manager setopt 'updates.enabled=0'"));
+    }
+
+    #[test]
+    fn chosen_packages_are_updated_with_dnf_upgrade() {
+        let mut config = test_config();
+        config.package_manager_path = Some(PathBuf::from("/opt/dnf/bin/dnf"));
+        config.update_command = String::from("dnf upgrade --refresh");
+        let manager = manager(&config);
+        let options = UpdateOptions { packages: vec![String::from("vim-enhanced"), String::from("git")], ..Default::default() };
+
+        let command = manager.get_selected_update_command(&options, false);
+        assert_eq!(manager.build_update_command(&command, &options, false).unwrap(), "'/opt/dnf/bin/dnf' upgrade -y 'vim-enhanced' 'git'");
+        let command = manager.get_selected_update_command(&options, true);
+        assert_eq!(manager.build_update_command(&command, &options, true).unwrap(), "'/opt/dnf/bin/dnf' upgrade 'vim-enhanced' 'git'");
+
+        assert_eq!(manager.get_selected_update_command(&UpdateOptions::default(), true), "dnf upgrade --refresh");
+    }
+
+    #[test]
+    fn security_update_command_uses_upgrade_security() {
+        let config = test_config();
+        let manager = manager(&config);
+        assert_eq!(manager.get_security_update_command(true).as_deref(), Some("dnf upgrade --security"));
+        assert_eq!(manager.get_security_update_command(false).as_deref(), Some("dnf upgrade -y --security"));
+    }
+
+    #[test]
+    fn built_in_commands_use_configured_dnf_path() {
+        let mut config = test_config();
+        config.package_manager_path = Some(PathBuf::from("/opt/dnf/bin/dnf"));
+        let manager = manager(&config);
+
+        assert_eq!(manager.get_security_update_command(false).as_deref(), Some("'/opt/dnf/bin/dnf' upgrade -y --security"));
+        assert_eq!(manager.get_repository_enabled_command("fedora", true).as_deref(),
+            Some("'/opt/dnf/bin/dnf' config-manager --set-enabled 'fedora'"));
+
+        // Configured commands may run dnf by name or by its path
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let command = manager.build_update_command("'/opt/dnf/bin/dnf' upgrade -y", &options, false).unwrap();
+        assert_eq!(command, "'/opt/dnf/bin/dnf' upgrade -y --best --setopt=allow_vendor_change=False");
+        assert!(manager.get_download_dir_command("dnf upgrade --downloadonly -y", Path::new("/tmp/updates")).is_some());
+    }
+
+    #[test]
+    fn download_dir_command_appends_downloaddir() {
+        let config = test_config();
+        let command = manager(&config).get_download_dir_command("dnf upgrade --downloadonly -y", Path::new("/tmp/updates"));
+        assert_eq!(command.as_deref(), Some("dnf upgrade --downloadonly -y --downloaddir '/tmp/updates'"));
+        assert_eq!(manager(&config).get_download_dir_command("./download.sh", Path::new("/tmp/updates")), None);
+    }
+
+    #[test]
+    fn check_update_output_is_parsed_regardless_of_locale() {
+        let output = "\
+Letzte Prüfung auf abgelaufene Metadaten: vor 0:12:34 am Mo 14 Okt 2024 10:00:00 CEST.
+
+glibc.x86_64                          2.39-22.fc40                    updates
+python3-a-very-long-package-name.noarch
+                                      1.2.3-1.fc40                    updates
+vim-enhanced.x86_64                   2:9.1.719-1.fc40                updates
+Veraltete Pakete
+vim-enhanced.x86_64                   2:9.1.719-1.fc40                updates
+    vim-minimal.x86_64                2:9.1.393-1.fc40                @updates
+";
+        let items = parse_check_update_output(output);
+        let names = items.iter().map(|item| item.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, ["glibc.x86_64", "python3-a-very-long-package-name.noarch", "vim-enhanced.x86_64"]);
+        assert_eq!(items[0].new_version.as_deref(), Some("2.39-22.fc40"));
+        assert_eq!(items[0].arch.as_deref(), Some("x86_64"));
+        assert_eq!(items[1].new_version.as_deref(), Some("1.2.3-1.fc40"));
+    }
+
+    #[test]
+    fn check_upgrade_output_of_dnf5_is_parsed() {
+        let output = "\
+Updating and loading repositories:
+ Fedora 41 - x86_64 - Updates           100% |  12.3 KiB/s |  10.2 KiB |  00m01s
+Repositories loaded.
+bash.x86_64                      5.2.32-1.fc41                updates
+kernel-core.x86_64               6.11.4-301.fc41              updates
+python3-a-very-long-package-name.noarch
+                                 1.2.3-1.fc41                 updates
+Obsoleting packages
+kernel-core.x86_64               6.11.4-301.fc41              updates
+    kernel-core.x86_64           6.11.3-300.fc41              @System
+";
+        let items = parse_check_update_output(output);
+        let names = items.iter().map(|item| item.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, ["bash.x86_64", "kernel-core.x86_64", "python3-a-very-long-package-name.noarch"]);
+        assert_eq!(items[1].new_version.as_deref(), Some("6.11.4-301.fc41"));
+        assert_eq!(items[2].arch.as_deref(), Some("noarch"));
+    }
+
+    #[test]
+    fn check_update_override_command_is_used() {
+        let mut config = test_config();
+        config.check_update_command = Some(String::from("printf '%s\\n' 'vim-enhanced.x86_64  2:9.1.719-1.fc40  updates'"));
+
+        let updates = manager(&config).check_update(false).unwrap();
+        assert_eq!(updates.items.iter().map(|item| item.name.as_str()).collect::<Vec<&str>>(), ["vim-enhanced.x86_64"]);
+        assert_eq!(updates.items[0].new_version.as_deref(), Some("2:9.1.719-1.fc40"));
+    }
+
+    #[test]
+    fn reboot_triggers_match_names_without_arch() {
+        let config = test_config();
+        let items = parse_check_update_output("\
+glibc.x86_64                          2.39-22.fc40                    updates
+vim-enhanced.x86_64                   2:9.1.719-1.fc40                updates
+");
+        assert!(manager(&config).reboot_triggered_by(&items[..1]));
+        assert!(!manager(&config).reboot_triggered_by(&items[1..]));
+    }
+
+    #[test]
+    fn excluded_packages_are_passed_as_exclude_args() {
+        let mut config = test_config();
+        config.ignored_packages = vec![String::from("kernel*")];
+        let options = UpdateOptions { exclude: vec![String::from("firefox")], ..Default::default() };
+        let command = manager(&config).build_update_command("dnf upgrade -y", &options, false).unwrap();
+        assert_eq!(command, "dnf upgrade -y '--exclude=kernel*' '--exclude=firefox'");
+    }
 
-        Ok(items)
+    #[test]
+    fn check_update_output_keeps_arch_of_each_update() {
+        let items = parse_check_update_output("\
+glibc.x86_64                          2.39-22.fc40                    updates
+glibc.i686                            2.39-22.fc40                    updates
+tzdata.noarch                         2024b-1.fc40                    updates
+");
+        let arches = items.iter().map(|item| item.arch.as_deref()).collect::<Vec<_>>();
+        assert_eq!(arches, [Some("x86_64"), Some("i686"), Some("noarch")]);
     }
 }
@@ -3,6 +3,7 @@ use std::io;
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     IO(io::Error),
     RPMError(rpm::Error),
@@ -11,18 +12,130 @@ pub enum Error {
     XMLError(quick_xml::errors::Error),
     XMLAttributeError(quick_xml::events::attributes::AttrError),
     RegexError(regex::Error),
-    NoChangelogsForPackage,
+    JSONError(serde_json::Error),
     NoChangelogsInDirectory,
-    PackageNameDoesNotMatch(String, String),
+    /// The directory exists, but doesn't contain any packages, e.g. because none have been downloaded yet
+    NoPackagesInDirectory,
+    DirectoryNotFound(String),
+    InvalidPackageFile(String, String),
+    NoMatchingPackages(Vec<String>),
+    NoSuchRepository(String),
+    PackageNameDoesNotMatch(String),
     InvalidRPMResponse,
     RPMCommandError(String),
     UnsupportedPackageManager,
+    /// An invalid package manager from the given source, such as an environment variable
+    InvalidPackageManager(&'static str, String),
+    Unsupported(&'static str),
     UnkownCachedPackagePath,
     EmptyCommand,
-    DownloadError(String),
-    UpdateError(String),
+    AllUpdatesExcluded,
+    /// A failed download, along with the command that was run, if any
+    DownloadError { command: Option<String>, stderr: String },
+    /// A failed update, along with the command that was run, if any
+    UpdateError { command: Option<String>, stderr: String },
+    Interrupted,
+    /// Another process holds the package manager's lock, along with the package manager's message
+    ManagerLocked(String),
+    /// An interactive command that didn't finish within the given number of seconds, along with its process id, since
+    /// it's left running
+    InputTimeout(u64, u32),
+    NoPolkitAgent,
+    ServiceRestartError(String),
+    HookError(String),
+    SnapshotError(String),
+    RepositoryError(String),
     ZypperError(String),
-    DnfError(String)
+    DnfError(String),
+    UnsupportedBundleVersion(u32),
+    VerificationFailed(usize),
+    /// The number of cached packages that couldn't be removed while cleaning the cache
+    CacheCleanupFailed(usize)
+}
+
+impl Error {
+    pub fn download_error(stderr: String) -> Self {
+        Error::DownloadError { command: None, stderr }
+    }
+
+    pub fn update_error(stderr: String) -> Self {
+        Error::UpdateError { command: None, stderr }
+    }
+
+    /// A stable identifier of the kind of error for scripts, which unlike the message doesn't change between versions
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::IO(_) => "IO",
+            Error::RPMError(_) => "RPM",
+            Error::Utf8StringError(_) => "INVALID_UTF8",
+            Error::ParseIntError(_) => "INVALID_NUMBER",
+            Error::XMLError(_) | Error::XMLAttributeError(_) => "INVALID_XML",
+            Error::RegexError(_) => "INVALID_REGEX",
+            Error::JSONError(_) => "JSON",
+            Error::NoChangelogsInDirectory => "NO_CHANGELOGS",
+            Error::NoPackagesInDirectory => "NO_PACKAGES",
+            Error::DirectoryNotFound(_) => "DIRECTORY_NOT_FOUND",
+            Error::InvalidPackageFile(_, _) => "INVALID_PACKAGE_FILE",
+            Error::NoMatchingPackages(_) => "NO_MATCHING_PACKAGES",
+            Error::NoSuchRepository(_) => "NO_SUCH_REPOSITORY",
+            Error::PackageNameDoesNotMatch(_) => "PACKAGE_NAME_DOES_NOT_MATCH",
+            Error::InvalidRPMResponse => "INVALID_RPM_RESPONSE",
+            Error::RPMCommandError(_) => "RPM_COMMAND_FAILED",
+            Error::UnsupportedPackageManager => "UNSUPPORTED_MANAGER",
+            Error::InvalidPackageManager(_, _) => "INVALID_MANAGER",
+            Error::Unsupported(_) => "UNSUPPORTED_OPERATION",
+            Error::UnkownCachedPackagePath => "UNKNOWN_CACHED_PACKAGE_PATH",
+            Error::EmptyCommand => "EMPTY_COMMAND",
+            Error::AllUpdatesExcluded => "ALL_UPDATES_EXCLUDED",
+            Error::DownloadError { .. } => "DOWNLOAD_FAILED",
+            Error::UpdateError { .. } => "UPDATE_FAILED",
+            Error::Interrupted => "INTERRUPTED",
+            Error::ManagerLocked(_) => "MANAGER_LOCKED",
+            Error::InputTimeout(_, _) => "INPUT_TIMEOUT",
+            Error::NoPolkitAgent => "NO_POLKIT_AGENT",
+            Error::ServiceRestartError(_) => "SERVICE_RESTART_FAILED",
+            Error::HookError(_) => "HOOK_FAILED",
+            Error::SnapshotError(_) => "SNAPSHOT_FAILED",
+            Error::RepositoryError(_) => "REPOSITORY_CHANGE_FAILED",
+            Error::ZypperError(_) => "ZYPPER_COMMAND_FAILED",
+            Error::DnfError(_) => "DNF_COMMAND_FAILED",
+            Error::UnsupportedBundleVersion(_) => "UNSUPPORTED_BUNDLE_VERSION",
+            Error::VerificationFailed(_) => "VERIFICATION_FAILED",
+            Error::CacheCleanupFailed(_) => "CACHE_CLEANUP_FAILED"
+        }
+    }
+
+    /// Records the `command` that failed in download and update errors, so that the error shows what was run.
+    /// Credentials in URLs, such as those of a proxy passed to pkexec, are left out.
+    pub fn with_command(self, command: &str) -> Self {
+        match self {
+            Error::DownloadError { stderr, .. } => Error::DownloadError { command: Some(redact_credentials(command)), stderr },
+            Error::UpdateError { stderr, .. } => Error::UpdateError { command: Some(redact_credentials(command)), stderr },
+            err => err
+        }
+    }
+}
+
+/// Replaces the `user:password@` part of URLs in `text` with `***@`
+fn redact_credentials(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(scheme_end) = rest.find("://") {
+        let (before, after) = rest.split_at(scheme_end + 3);
+        redacted.push_str(before);
+
+        let authority_end = after.find(|c: char| c == '/' || c == '\'' || c == '"' || c.is_whitespace()).unwrap_or(after.len());
+        match after[..authority_end].rfind('@') {
+            Some(at) => {
+                redacted.push_str("***");
+                rest = &after[at..];
+            },
+            None => rest = after
+        }
+    }
+
+    redacted.push_str(rest);
+    redacted
 }
 
 impl From<io::Error> for Error {
@@ -67,6 +180,12 @@ impl From<regex::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::JSONError(value)
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
@@ -77,6 +196,7 @@ impl std::error::Error for Error {
             Error::XMLError(err) => Some(err),
             Error::XMLAttributeError(err) => Some(err),
             Error::RegexError(err) => Some(err),
+            Error::JSONError(err) => Some(err),
             _ => None
         }
     }
@@ -92,18 +212,120 @@ impl std::fmt::Display for Error {
             Error::XMLError(err) => err.fmt(f),
             Error::XMLAttributeError(err) => err.fmt(f),
             Error::RegexError(err) => err.fmt(f),
-            Error::NoChangelogsForPackage => write!(f, "package has no changelogs to display"),
+            Error::JSONError(err) => err.fmt(f),
             Error::NoChangelogsInDirectory => write!(f, "could not find any packages containing changelogs"),
-            Error::PackageNameDoesNotMatch(name, query) => write!(f, "package '{}' does not match the query '{}'", name, query),
+            Error::NoPackagesInDirectory => write!(f, "no packages have been downloaded yet, run `package-assistant check-update --download` first"),
+            Error::DirectoryNotFound(path) => write!(f, "package directory '{}' does not exist", path),
+            Error::InvalidPackageFile(path, error_string) => write!(f, "'{}' is not a valid package: {}", path, error_string),
+            Error::NoMatchingPackages(suggestions) => write!(f, "no packages match the query, did you mean: {}?", suggestions.join(", ")),
+            Error::NoSuchRepository(name) => write!(f, "no repository named '{}', see list-repos for the configured ones", name),
+            Error::PackageNameDoesNotMatch(name) => write!(f, "package '{}' does not match the query", name),
             Error::RPMCommandError(error_string) => write!(f, "rpm command failed: {}", error_string),
             Error::InvalidRPMResponse => write!(f, "rpm query returned an unexpected response"),
             Error::UnsupportedPackageManager => write!(f, "'package_manager' in settings is either empty or not supported"),
+            Error::InvalidPackageManager(source, message) => write!(f, "{}: {}", source, message),
+            Error::Unsupported(operation) => write!(f, "{} is not supported by this package manager", operation),
             Error::UnkownCachedPackagePath => write!(f, "'cached_package_path' must be provided in settings"),
             Error::EmptyCommand => write!(f, "update and download commands must be provided in settings"),
-            Error::DownloadError(error_string) => write!(f, "failed to download packages: {}", error_string),
-            Error::UpdateError(error_string) => write!(f, "failed to run update: {}", error_string),
+            Error::AllUpdatesExcluded => write!(f, "all available updates are excluded"),
+            Error::DownloadError { command: Some(command), stderr } => write!(f, "failed to download packages with `{}`: {}", command, stderr),
+            Error::DownloadError { command: None, stderr } => write!(f, "failed to download packages: {}", stderr),
+            Error::UpdateError { command: Some(command), stderr } => write!(f, "failed to run update with `{}`: {}", command, stderr),
+            Error::UpdateError { command: None, stderr } => write!(f, "failed to run update: {}", stderr),
+            Error::Interrupted => write!(f, "update was interrupted"),
+            Error::ManagerLocked(stderr) => write!(f, "another package operation is running, e.g. in PackageKit, try again once it has finished: {}", stderr.trim()),
+            Error::InputTimeout(seconds, _) => write!(f, "update did not finish within {} seconds, it may be waiting for input", seconds),
+            Error::NoPolkitAgent => write!(f, "pkexec cannot ask for a password without a terminal or graphical session, run as root or set 'sudo_fallback' in settings"),
+            Error::ServiceRestartError(error_string) => write!(f, "failed to restart services: {}", error_string),
+            Error::HookError(error_string) => write!(f, "update hook failed: {}", error_string),
+            Error::SnapshotError(error_string) => write!(f, "failed to create snapshot: {}", error_string),
+            Error::RepositoryError(error_string) => write!(f, "failed to change repository: {}", error_string),
             Error::ZypperError(error_string) => write!(f, "zypper command failed: {}", error_string),
             Error::DnfError(error_string) => write!(f, "dnf command failed: {}", error_string),
+            Error::VerificationFailed(count) => write!(f, "{} cached packages failed verification", count),
+            Error::CacheCleanupFailed(count) => write!(f, "{} cached packages couldn't be removed", count),
+            Error::UnsupportedBundleVersion(version) => write!(f, "bundle version {} is not supported by this version of package-assistant", version),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// One error of every kind. Fails to compile when a variant is added, so that it's added here too.
+    fn every_error() -> Vec<Error> {
+        let errors = vec![
+            Error::IO(io::Error::other("test")),
+            Error::RPMError(rpm::Error::UnsupportedHeaderVersion(2)),
+            Error::Utf8StringError(String::from_utf8(vec![0xff]).unwrap_err()),
+            Error::ParseIntError("x".parse::<u64>().unwrap_err()),
+            Error::XMLError(quick_xml::errors::Error::from(io::Error::other("test"))),
+            Error::RegexError(regex::Error::Syntax(String::new())),
+            Error::JSONError(serde_json::from_str::<u64>("x").unwrap_err()),
+            Error::NoChangelogsInDirectory,
+            Error::NoPackagesInDirectory,
+            Error::DirectoryNotFound(String::new()),
+            Error::InvalidPackageFile(String::new(), String::new()),
+            Error::NoMatchingPackages(Vec::new()),
+            Error::NoSuchRepository(String::new()),
+            Error::PackageNameDoesNotMatch(String::new()),
+            Error::InvalidRPMResponse,
+            Error::RPMCommandError(String::new()),
+            Error::UnsupportedPackageManager,
+            Error::InvalidPackageManager("", String::new()),
+            Error::Unsupported(""),
+            Error::UnkownCachedPackagePath,
+            Error::EmptyCommand,
+            Error::AllUpdatesExcluded,
+            Error::download_error(String::new()),
+            Error::update_error(String::new()),
+            Error::Interrupted,
+            Error::ManagerLocked(String::new()),
+            Error::InputTimeout(0, 0),
+            Error::NoPolkitAgent,
+            Error::ServiceRestartError(String::new()),
+            Error::HookError(String::new()),
+            Error::SnapshotError(String::new()),
+            Error::RepositoryError(String::new()),
+            Error::ZypperError(String::new()),
+            Error::DnfError(String::new()),
+            Error::UnsupportedBundleVersion(0),
+            Error::VerificationFailed(0),
+            Error::CacheCleanupFailed(0)
+        ];
+
+        for err in &errors {
+            match err {
+                // Both are invalid XML, so they share a code
+                Error::XMLError(_) | Error::XMLAttributeError(_) => (),
+                Error::IO(_) | Error::RPMError(_) | Error::Utf8StringError(_) | Error::ParseIntError(_)
+                | Error::RegexError(_) | Error::JSONError(_) | Error::NoChangelogsInDirectory | Error::NoPackagesInDirectory
+                | Error::DirectoryNotFound(_) | Error::InvalidPackageFile(_, _) | Error::NoMatchingPackages(_)
+                | Error::NoSuchRepository(_) | Error::PackageNameDoesNotMatch(_) | Error::InvalidRPMResponse
+                | Error::RPMCommandError(_) | Error::UnsupportedPackageManager | Error::InvalidPackageManager(_, _)
+                | Error::Unsupported(_) | Error::UnkownCachedPackagePath | Error::EmptyCommand | Error::AllUpdatesExcluded
+                | Error::DownloadError { .. } | Error::UpdateError { .. } | Error::Interrupted | Error::ManagerLocked(_)
+                | Error::InputTimeout(_, _) | Error::NoPolkitAgent | Error::ServiceRestartError(_) | Error::HookError(_)
+                | Error::SnapshotError(_) | Error::RepositoryError(_) | Error::ZypperError(_) | Error::DnfError(_)
+                | Error::UnsupportedBundleVersion(_) | Error::VerificationFailed(_) | Error::CacheCleanupFailed(_) => ()
+            }
+        }
+
+        errors
+    }
+
+    #[test]
+    fn every_error_has_a_distinct_code() {
+        let mut codes: HashMap<&str, String> = HashMap::new();
+        for err in every_error() {
+            let code = err.code();
+            assert!(!code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'), "{}", code);
+            if let Some(other) = codes.insert(code, format!("{:?}", err)) {
+                panic!("{} is used by both {} and {:?}", code, other, err);
+            }
+        }
+    }
+}
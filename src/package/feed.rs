@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use quick_xml::escape::escape;
+
+use crate::storage::PackageConfig;
+
+use super::package_manager::{get_changelog_date_format, get_changelog_timezone};
+use super::{ChangelogEntry, PackageChangelogResult};
+
+const FEED_ID: &str = "tag:package-assistant,2024:changelogs";
+
+/// Formats the changelogs as an Atom feed. With `entry_per_changelog`, every changelog entry becomes a feed entry,
+/// and otherwise there's one feed entry per package with all of its changelog entries as content.
+pub fn format_changelog_results_atom(config: &PackageConfig, results: &[PackageChangelogResult], entry_per_changelog: bool) -> String {
+    let date_format = get_changelog_date_format(config);
+    let timezone = get_changelog_timezone(config);
+    let mut entries = Vec::new();
+
+    for result in results {
+        if entry_per_changelog {
+            for changelog in &result.changelogs {
+                let summary = changelog.description.lines().next().unwrap_or_default().trim_start_matches(['-', ' ']);
+                entries.push(format_entry(
+                    &get_entry_id(&result.name, changelog),
+                    &format!("{}: {}", result.name, summary),
+                    changelog.timestamp,
+                    changelog.author.as_deref(),
+                    &changelog.description
+                ));
+            }
+        } else if let Some(newest) = result.changelogs.iter().max_by_key(|changelog| changelog.timestamp) {
+            let content = result.changelogs.iter()
+                .map(|changelog| changelog.format(date_format, timezone))
+                .collect::<Vec<String>>()
+                .join("\n\n");
+            entries.push(format_entry(&get_entry_id(&result.name, newest), &result.name, newest.timestamp, None, &content));
+        }
+    }
+
+    // The feed was last updated by its newest entry, or now if it has none
+    let updated = results.iter()
+        .flat_map(|result| result.changelogs.iter().map(|changelog| changelog.timestamp))
+        .max()
+        .map_or_else(|| Utc::now().to_rfc3339(), format_timestamp);
+
+    format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <id>{}</id>\n\
+        <title>Package changelogs</title>\n\
+        <updated>{}</updated>\n\
+        <author><name>package-assistant</name></author>\n\
+        <generator>package-assistant</generator>\n\
+        {}</feed>", FEED_ID, updated, entries.concat())
+}
+
+/// Identifies the feed entry for the `changelog` of package `name` by the version it was written for, so that a feed
+/// reader shows every update as a new entry. rpm changelogs end the author with ` - <version>`, and otherwise the
+/// timestamp is used instead.
+fn get_entry_id(name: &str, changelog: &ChangelogEntry) -> String {
+    let version = changelog.author.as_deref()
+        .and_then(|author| author.rsplit_once(" - "))
+        .map(|(_, version)| version.trim())
+        .filter(|version| !version.is_empty() && !version.contains(char::is_whitespace));
+
+    match version {
+        Some(version) => format!("{}/{}", name, version),
+        None => format!("{}/{}", name, changelog.timestamp)
+    }
+}
+
+fn format_entry(id: &str, title: &str, timestamp: u64, author: Option<&str>, content: &str) -> String {
+    let date = format_timestamp(timestamp);
+    let author = author
+        .map(|author| format!("<author><name>{}</name></author>\n", escape(author)))
+        .unwrap_or_default();
+
+    format!("<entry>\n\
+        <id>{}/{}</id>\n\
+        <title>{}</title>\n\
+        <published>{}</published>\n\
+        <updated>{}</updated>\n\
+        {}<content type=\"text\">{}</content>\n\
+        </entry>\n", FEED_ID, escape(id), escape(title), date, date, author, escape(content))
+}
+
+/// Formats a Unix timestamp as an RFC 3339 date, as Atom requires
+fn format_timestamp(timestamp: u64) -> String {
+    i64::try_from(timestamp).ok()
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+
+    use crate::storage::Config;
+
+    use super::*;
+
+    fn changelog(timestamp: u64, author: &str, description: &str) -> ChangelogEntry {
+        ChangelogEntry { timestamp, author: Some(String::from(author)), description: String::from(description) }
+    }
+
+    /// Reads the text of every element called `name`, failing if the XML isn't well-formed
+    fn read_elements(xml: &str, name: &[u8]) -> Vec<String> {
+        let mut reader = Reader::from_str(xml);
+        let mut texts = Vec::new();
+        let mut in_element = false;
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Start(start) => in_element = start.name().as_ref() == name,
+                Event::Text(text) if in_element => texts.push(text.unescape().unwrap().into_owned()),
+                Event::End(_) => in_element = false,
+                Event::Eof => break,
+                _ => ()
+            }
+        }
+
+        texts
+    }
+
+    fn results() -> Vec<PackageChangelogResult> {
+        vec![PackageChangelogResult {
+            name: String::from("vim"),
+            changelogs: vec![
+                changelog(1704888000, "Jane Doe <jane@example.com> - 9.1-2", "- Fix <script> & \"quotes\""),
+                changelog(1704110400, "Jane Doe <jane@example.com> - 9.1-1", "- Update to 9.1"),
+                changelog(1701432000, "John Doe <john@example.com>", "- Update to 9.0")
+            ]
+        }]
+    }
+
+    #[test]
+    fn feed_is_well_formed_with_escaped_descriptions() {
+        let config = Config::default().package;
+        let feed = format_changelog_results_atom(&config, &results(), true);
+
+        let contents = read_elements(&feed, b"content");
+        assert_eq!(contents[0], "- Fix <script> & \"quotes\"");
+        assert_eq!(read_elements(&feed, b"published")[0], "2024-01-10T12:00:00+00:00");
+    }
+
+    #[test]
+    fn entry_ids_are_specific_to_versions() {
+        let config = Config::default().package;
+
+        let feed = format_changelog_results_atom(&config, &results(), true);
+        let ids = read_elements(&feed, b"id");
+        assert_eq!(ids, [
+            FEED_ID,
+            "tag:package-assistant,2024:changelogs/vim/9.1-2",
+            "tag:package-assistant,2024:changelogs/vim/9.1-1",
+            "tag:package-assistant,2024:changelogs/vim/1701432000"
+        ]);
+
+        let feed = format_changelog_results_atom(&config, &results(), false);
+        assert_eq!(read_elements(&feed, b"id")[1], "tag:package-assistant,2024:changelogs/vim/9.1-2");
+    }
+}
@@ -1,9 +1,17 @@
 mod package_manager;
 mod error;
 mod utilities;
+mod rpm;
 mod zypper;
 mod dnf;
+mod bundle;
+mod cache;
+mod registry;
+mod feed;
 
 pub use package_manager::*;
 pub use error::Error;
-pub use utilities::get_package_manager;
\ No newline at end of file
+pub use bundle::UpdateBundle;
+pub use feed::format_changelog_results_atom;
+pub use registry::get_package_manager;
+pub use utilities::{disable_privilege_elevation, is_privilege_elevation_enabled, matches_pattern, parse_remote_path, set_interactive_timeout, tool_version};
\ No newline at end of file
@@ -1,24 +1,257 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-use crate::storage::PackageConfig;
+use chrono::{DateTime, FixedOffset, Local};
+use chrono::format::StrftimeItems;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
+use crate::storage::{PackageConfig, DEFAULT_CHANGELOG_DATE_FORMAT};
+
+use super::cache::ChangelogCache;
 use super::{utilities, Error};
 use super::error::Result;
 
+#[derive(Default)]
 pub struct ChangelogQuery {
-    pub name: Option<String>
+    /// Prefix that package names must start with
+    pub name: Option<String>,
+    /// Exact package names to include
+    pub names: Option<HashSet<String>>,
+    /// If set, `name` also matches packages whose names start with a string within this edit distance of it
+    pub fuzzy_max_distance: Option<usize>,
+    /// Names of the installed packages, if only those should be included
+    pub installed: Option<HashSet<String>>,
+    /// If set, only changelog entries newer than this Unix timestamp are included
+    pub since: Option<u64>,
+    /// Reads every package instead of using the changelog index, and leaves the index unchanged
+    pub skip_index: bool,
+    /// Prints the number of packages scanned so far to stderr while scanning a directory
+    pub show_progress: bool
+}
+
+#[derive(Default)]
+pub struct UpdateOptions {
+    /// Package name patterns to exclude from this update, in addition to `ignored_packages` in settings
+    pub exclude: Vec<String>,
+    /// Also installs updates that the package manager holds back by default, such as newly recommended packages
+    pub include_optional: bool,
+    /// Makes a non-interactive update fail instead of letting the package manager resolve conflicts by changing
+    /// vendors, downgrading or removing packages
+    pub fail_on_conflict: bool,
+    /// Only updates these packages instead of all available updates
+    pub packages: Vec<String>
+}
+
+pub struct UpdatePreview {
+    /// The package manager's output describing the proposed transaction
+    pub transaction: String,
+    /// Lines of the output that describe conflicts or other problems
+    pub conflicts: Vec<String>
+}
+
+/// What was left out while scanning a directory for changelogs
+#[derive(Default)]
+pub struct DirScan {
+    /// Names of the packages that didn't match the query
+    pub unmatched_names: BTreeSet<String>,
+    /// Paths that couldn't be read
+    pub skipped_paths: Vec<PathBuf>,
+    /// Package files that couldn't be parsed, along with the error
+    pub failed_packages: Vec<(PathBuf, String)>,
+    /// Number of package files that were found, whether or not they match the query
+    pub package_count: usize,
+    /// Why the changelog index couldn't be saved after the scan, if it couldn't
+    pub index_error: Option<Error>
 }
 
+/// The package files that were deleted while cleaning the cache
+#[derive(Default)]
+pub struct CacheCleanup {
+    pub removed_count: usize,
+    pub freed_bytes: u64,
+    /// Files that couldn't be deleted, which don't stop the rest from being cleaned
+    pub failed: Vec<(PathBuf, io::Error)>
+}
+
+pub struct PackageVerificationResult {
+    pub path: PathBuf,
+    pub valid: bool,
+    /// Output of the verification tool, describing which checks passed or failed
+    pub message: String
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct PackageChangelogResult {
     pub name: String,
-    pub changelogs: Vec<String>
+    pub changelogs: Vec<ChangelogEntry>
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ChangelogEntry {
+    /// Unix timestamp of the entry
+    pub timestamp: u64,
+    /// Author of the entry, usually including their email address and the package version
+    pub author: Option<String>,
+    pub description: String
+}
+
+/// The timezone that changelog dates are shown in
+#[derive(Clone, Copy, Default)]
+pub enum ChangelogTimezone {
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset)
+}
+
+impl ChangelogTimezone {
+    /// Parses `local`, `UTC` or a fixed offset such as `+02:00`
+    pub fn parse(timezone: &str) -> Option<Self> {
+        match timezone {
+            _ if timezone.eq_ignore_ascii_case("local") => Some(ChangelogTimezone::Local),
+            _ if timezone.eq_ignore_ascii_case("utc") => Some(ChangelogTimezone::Utc),
+            _ => timezone.parse::<FixedOffset>().ok().map(ChangelogTimezone::Fixed)
+        }
+    }
+
+    /// Formats a Unix timestamp in this timezone with the given strftime `date_format`
+    pub fn format_timestamp(&self, timestamp: u64, date_format: &str) -> String {
+        i64::try_from(timestamp).ok()
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map(|date| match self {
+                ChangelogTimezone::Local => date.with_timezone(&Local).format(date_format).to_string(),
+                ChangelogTimezone::Utc => date.format(date_format).to_string(),
+                ChangelogTimezone::Fixed(offset) => date.with_timezone(offset).format(date_format).to_string()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl ChangelogEntry {
+    /// Formats the entry like `rpm --changelog`, below a line with its date in the given strftime `date_format`
+    /// and its author
+    pub fn format(&self, date_format: &str, timezone: ChangelogTimezone) -> String {
+        let date = timezone.format_timestamp(self.timestamp, date_format);
+
+        match self.author {
+            Some(ref author) => format!("* {} {}\n{}", date, author, self.description),
+            None => format!("* {}\n{}", date, self.description)
+        }
+    }
+}
+
+/// The number of changelog entries of a package and the date of the newest one, without the entries themselves
+#[derive(Serialize)]
+pub struct ChangelogSummaryItem {
+    pub name: String,
+    pub count: usize,
+    /// Unix timestamp of the newest entry
+    pub newest_timestamp: u64
 }
 
+#[derive(Deserialize, Serialize)]
 pub struct PackageUpdateItem {
     pub name: String,
     pub old_version: Option<String>,
-    pub new_version: Option<String>
+    pub new_version: Option<String>,
+    /// The kind of update according to its advisory, e.g. `security` or `bugfix`
+    #[serde(default)]
+    pub category: Option<String>,
+    /// The architecture of the new version, e.g. `x86_64` or `noarch`
+    #[serde(default)]
+    pub arch: Option<String>
+}
+
+/// A patch, which groups the package updates that fix an issue, as used by zypper
+#[derive(Deserialize, Serialize)]
+pub struct PatchItem {
+    pub name: String,
+    /// The kind of patch, e.g. `security` or `recommended`
+    pub category: Option<String>,
+    pub severity: Option<String>,
+    /// Whether the system needs to be rebooted after applying the patch
+    pub needs_reboot: bool
+}
+
+/// A repository that packages are installed and updated from
+#[derive(Deserialize, Serialize)]
+pub struct RepoInfo {
+    /// The short name that identifies the repository, e.g. zypper's alias or dnf's repo id
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    /// The priority of the repository, where lower values are preferred, if the package manager reports it
+    pub priority: Option<i32>
+}
+
+impl std::fmt::Display for RepoInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}), {}", self.name, self.id, if self.enabled { "enabled" } else { "disabled" })?;
+
+        if let Some(priority) = self.priority {
+            write!(f, ", priority {}", priority)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PatchItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+
+        let details = [self.category.as_deref(), self.severity.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&str>>();
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+
+        if self.needs_reboot {
+            write!(f, " [reboot required]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The available updates, along with totals that are computed once when they're listed
+#[derive(Deserialize, Serialize)]
+pub struct UpdateSummary {
+    pub items: Vec<PackageUpdateItem>,
+    pub total: usize,
+    pub security_count: usize
+}
+
+impl UpdateSummary {
+    /// Sorts the updates by name, ignoring case, so that they're listed in the same order on every run regardless of
+    /// the order the package manager printed them in
+    pub fn new(mut items: Vec<PackageUpdateItem>) -> Self {
+        items.sort_by_cached_key(|item| (item.name.to_lowercase(), item.name.clone()));
+        let security_count = items.iter()
+            .filter(|item| item.category.as_deref() == Some("security"))
+            .count();
+
+        Self { total: items.len(), security_count, items }
+    }
+}
+
+impl PackageUpdateItem {
+    /// The package name without the `.arch` suffix that dnf lists updates with
+    pub fn base_name(&self) -> &str {
+        self.arch.as_deref()
+            .and_then(|arch| self.name.strip_suffix(arch)?.strip_suffix('.'))
+            .unwrap_or(&self.name)
+    }
 }
 
 impl std::fmt::Display for PackageUpdateItem {
@@ -33,88 +266,1260 @@ impl std::fmt::Display for PackageUpdateItem {
     }
 }
 
-pub trait PackageManager {
+/// Formats the changelogs of a single package below the header configured in settings, with dates in the given
+/// strftime `date_format` and `timezone`. If the header is empty, it's left out entirely rather than leaving a blank line.
+pub fn format_package_changelogs(config: &PackageConfig, result: &PackageChangelogResult, date_format: &str,
+    timezone: ChangelogTimezone) -> String {
+    let header = config.changelog_header_format.replace("{name}", &result.name);
+    let lines = std::iter::once(header)
+        .filter(|header| !header.is_empty())
+        .chain(result.changelogs.iter().map(|changelog| changelog.format(date_format, timezone)))
+        .collect::<Vec<String>>();
+
+    lines.join("\n")
+}
+
+/// Formats the changelogs of each package, separated by the separator configured in settings
+pub fn format_changelog_results(config: &PackageConfig, results: &[PackageChangelogResult]) -> String {
+    let date_format = get_changelog_date_format(config);
+    let timezone = get_changelog_timezone(config);
+    let mut changelog_string = String::new();
+    for (i, result) in results.iter().enumerate() {
+        if i > 0 {
+            changelog_string.push_str(&config.changelog_separator);
+        }
+        changelog_string.push_str(&format_package_changelogs(config, result, date_format, timezone));
+    }
+
+    changelog_string
+}
+
+/// Gets `changelog_date_format` from settings, falling back to the default with a warning if it isn't a valid
+/// strftime format
+pub(super) fn get_changelog_date_format(config: &PackageConfig) -> &str {
+    if StrftimeItems::new(&config.changelog_date_format).parse().is_ok() {
+        &config.changelog_date_format
+    } else {
+        eprintln!("Warning: invalid changelog_date_format '{}', using '{}' instead",
+            config.changelog_date_format, DEFAULT_CHANGELOG_DATE_FORMAT);
+        DEFAULT_CHANGELOG_DATE_FORMAT
+    }
+}
+
+/// Gets `changelog_timezone` from settings, falling back to the local timezone with a warning if it isn't valid
+pub(super) fn get_changelog_timezone(config: &PackageConfig) -> ChangelogTimezone {
+    let Some(ref timezone) = config.changelog_timezone else {
+        return ChangelogTimezone::Local
+    };
+
+    ChangelogTimezone::parse(timezone).unwrap_or_else(|| {
+        eprintln!("Warning: invalid changelog_timezone '{}', using the local timezone instead", timezone);
+        ChangelogTimezone::Local
+    })
+}
+
+/// Returns the changelog entries in `new_results` that aren't in `old_results`, grouped by package name. Entries are
+/// compared by their package name, timestamp and description.
+pub fn diff_changelog_results(old_results: &[PackageChangelogResult], new_results: Vec<PackageChangelogResult>) -> Vec<PackageChangelogResult> {
+    let mut seen_entries = old_results.iter()
+        .flat_map(|result| result.changelogs.iter().map(|entry| (result.name.clone(), entry.timestamp, entry.description.clone())))
+        .collect::<HashSet<(String, u64, String)>>();
+
+    let mut packages: BTreeMap<String, Vec<ChangelogEntry>> = BTreeMap::new();
+    for result in new_results {
+        for entry in result.changelogs {
+            // The same entry shows up once per version of a package
+            if seen_entries.insert((result.name.clone(), entry.timestamp, entry.description.clone())) {
+                packages.entry(result.name.clone()).or_default().push(entry);
+            }
+        }
+    }
+
+    packages.into_iter()
+        .map(|(name, changelogs)| PackageChangelogResult { name, changelogs })
+        .collect()
+}
+
+/// Keeps only the changelog entries whose description matches `pattern`, leaving out packages without any
+pub fn grep_changelog_results(results: Vec<PackageChangelogResult>, pattern: &Regex) -> Vec<PackageChangelogResult> {
+    results.into_iter()
+        .filter_map(|mut result| {
+            result.changelogs.retain(|entry| pattern.is_match(&entry.description));
+            (!result.changelogs.is_empty()).then_some(result)
+        })
+        .collect()
+}
+
+/// Groups the changelog entries by package name, as they're serialized in the JSON format with the package names as
+/// keys and their changelog entries as values
+pub fn get_changelog_entries_by_name(results: &[PackageChangelogResult]) -> BTreeMap<&str, Vec<&ChangelogEntry>> {
+    let mut packages: BTreeMap<&str, Vec<&ChangelogEntry>> = BTreeMap::new();
+    for result in results {
+        packages.entry(result.name.as_str())
+            .or_default()
+            .extend(result.changelogs.iter());
+    }
+
+    packages
+}
+
+/// Counts the changelog entries of each package, leaving out packages without any
+pub fn summarize_changelog_results(results: &[PackageChangelogResult]) -> Vec<ChangelogSummaryItem> {
+    results.iter()
+        .filter_map(|result| Some(ChangelogSummaryItem {
+            name: result.name.clone(),
+            count: result.changelogs.len(),
+            newest_timestamp: result.changelogs.iter().map(|changelog| changelog.timestamp).max()?
+        }))
+        .collect()
+}
+
+/// Formats the summary as a table with a row per package, which has its name, number of changelog entries and the
+/// date of the newest one
+pub fn format_changelog_summary(config: &PackageConfig, summary: &[ChangelogSummaryItem]) -> String {
+    let date_format = get_changelog_date_format(config);
+    let timezone = get_changelog_timezone(config);
+    let name_width = summary.iter().map(|item| item.name.len()).max().unwrap_or_default().max("Package".len());
+
+    let mut lines = vec![format!("{:<name_width$}  {:>7}  Newest entry", "Package", "Entries")];
+    lines.extend(summary.iter().map(|item| format!("{:<name_width$}  {:>7}  {}", item.name, item.count,
+        timezone.format_timestamp(item.newest_timestamp, date_format))));
+    lines.join("\n")
+}
+
+/// Formats the changelogs as Markdown, with a heading for each package above its changelog entries in a code block
+pub fn format_changelog_results_markdown(config: &PackageConfig, results: &[PackageChangelogResult]) -> String {
+    let date_format = get_changelog_date_format(config);
+    let timezone = get_changelog_timezone(config);
+
+    results.iter()
+        .map(|result| {
+            let changelogs = result.changelogs.iter()
+                .map(|changelog| changelog.format(date_format, timezone))
+                .collect::<Vec<String>>();
+            format!("## {}\n\n```\n{}\n```", result.name, changelogs.join("\n\n"))
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+/// Recursively lists the files in `path` with one of the package `extensions` in a stable order. Other files, such as
+/// repository metadata, are left out. Returns an error only if `path` itself can't be read, while unreadable entries
+/// below it are added to `scan`.
+fn find_scan_files(path: &Path, extensions: &[&str], scan: &mut DirScan) -> Result<Vec<PathBuf>> {
+    let dir_entries = fs::read_dir(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => Error::DirectoryNotFound(path.display().to_string()),
+        _ => Error::IO(err)
+    })?;
+
+    // An entry that fails to be read has no name to report, and the rest of the directory usually can't be read
+    // either, so the directory is reported once and the entries read so far are kept
+    let mut entries = Vec::new();
+    for item in dir_entries {
+        match item {
+            Ok(entry) => entries.push(entry),
+            Err(_) => {
+                scan.skipped_paths.push(path.to_owned());
+                break
+            }
+        }
+    }
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry_path = entry.path();
+
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => match find_scan_files(&entry_path, extensions, scan) {
+                Ok(dir_files) => files.extend(dir_files),
+                Err(_) => scan.skipped_paths.push(entry_path)
+            },
+            Ok(_) if utilities::has_package_extension(&entry.file_name().to_string_lossy(), extensions) => files.push(entry_path),
+            Ok(_) => (),
+            Err(_) => scan.skipped_paths.push(entry_path)
+        }
+    }
+
+    Ok(files)
+}
+
+/// Lists the package files in all of the directories in `paths` like `find_scan_files`. A package file that's in more
+/// than one directory, including subdirectories of the same path, is only listed once, from the directory where it was
+/// modified last. Directories that can't be
+/// read are added to `scan`, and an error is only returned if none of them can be read.
+fn find_scan_files_in_dirs(paths: &[PathBuf], extensions: &[&str], scan: &mut DirScan) -> Result<Vec<PathBuf>> {
+    let mut newest_files: HashMap<OsString, (PathBuf, Option<SystemTime>)> = HashMap::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let files = match find_scan_files(path, extensions, scan) {
+            Ok(files) => files,
+            Err(err) => {
+                scan.skipped_paths.push(path.to_owned());
+                errors.push(err);
+                continue
+            }
+        };
+
+        for file in files {
+            let Some(file_name) = file.file_name().map(OsStr::to_owned) else { continue };
+            let modified = fs::metadata(&file).and_then(|metadata| metadata.modified()).ok();
+            match newest_files.get(&file_name) {
+                Some((_, newest_modified)) if *newest_modified >= modified => (),
+                _ => { newest_files.insert(file_name, (file, modified)); }
+            }
+        }
+    }
+
+    if errors.len() == paths.len() {
+        if let Some(err) = errors.pop() {
+            return Err(err)
+        }
+    }
+
+    let mut files = newest_files.into_values().map(|(file, _)| file).collect::<Vec<PathBuf>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Package managers are shared between threads while scanning for changelogs, hence `Sync`
+pub trait PackageManager: Sync {
     fn get_cached_changelogs(&self, query: &ChangelogQuery) -> Result<String> {
-        if let Some(ref path) = self.get_config().cached_package_path {
-            self.get_dir_changelogs(query, path)
+        let results = self.get_cached_changelog_results(query)?;
+        Ok(format_changelog_results(self.get_config(), &results))
+    }
+
+    /// Gets the changelogs of the packages in `cached_package_path`. Remote `sftp://host/path` locations are first
+    /// mirrored into the local cache directory with rsync.
+    fn get_cached_changelog_results(&self, query: &ChangelogQuery) -> Result<Vec<PackageChangelogResult>> {
+        let paths = self.get_cached_package_dirs()?;
+        self.get_dirs_changelog_results(query, &paths)
+    }
+
+    /// Gets the local directories of `cached_package_path`, mirroring remote `sftp://host/path` locations first
+    fn get_cached_package_dirs(&self) -> Result<Vec<PathBuf>> {
+        let config = self.get_config();
+        let paths = config.cached_package_path.as_ref().ok_or(Error::UnkownCachedPackagePath)?.paths();
+
+        paths.iter()
+            .map(|path| match utilities::parse_remote_path(path) {
+                Some(remote) => utilities::sync_remote_packages(config, &remote),
+                None => Ok(path.to_owned())
+            })
+            .collect()
+    }
+
+    /// Deletes the package files in the local directories of `cached_package_path` that were last modified longer than
+    /// `older_than` ago, or all of them if it's `None`. With `keep_latest`, the most recently downloaded file of each
+    /// package is kept regardless of its age. Remote `sftp://host/path` locations are left alone.
+    fn clean_cache(&self, older_than: Option<Duration>, keep_latest: bool) -> Result<CacheCleanup> {
+        let config = self.get_config();
+        let paths = config.cached_package_path.as_ref().ok_or(Error::UnkownCachedPackagePath)?.paths();
+        let mut scan = DirScan::default();
+        let mut files = Vec::new();
+        for path in paths.iter().filter(|path| utilities::parse_remote_path(path).is_none()) {
+            for file in find_scan_files(path, self.package_extensions(), &mut scan)? {
+                if let Ok(metadata) = fs::metadata(&file) {
+                    files.push((file, metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len()));
+                }
+            }
+        }
+
+        let mut latest_files: HashMap<&str, (&Path, SystemTime)> = HashMap::new();
+        if keep_latest {
+            for (file, modified, _) in &files {
+                let name = file.file_name().and_then(OsStr::to_str).map_or("", utilities::get_package_name_from_file_name);
+                match latest_files.get(name) {
+                    Some((_, latest_modified)) if latest_modified >= modified => (),
+                    _ => { latest_files.insert(name, (file, *modified)); }
+                }
+            }
+        }
+
+        let latest_files = latest_files.into_values().map(|(file, _)| file).collect::<HashSet<&Path>>();
+        let cutoff = older_than.and_then(|age| SystemTime::now().checked_sub(age));
+        let mut cleanup = CacheCleanup::default();
+        for (file, modified, size) in &files {
+            let is_latest = latest_files.contains(file.as_path());
+            let is_recent = cutoff.is_some_and(|cutoff| *modified >= cutoff);
+            if is_latest || is_recent {
+                continue
+            }
+
+            match fs::remove_file(file) {
+                Ok(()) => {
+                    cleanup.removed_count += 1;
+                    cleanup.freed_bytes += size;
+                },
+                Err(err) => cleanup.failed.push((file.clone(), err))
+            }
+        }
+
+        Ok(cleanup)
+    }
+
+    /// Reads every package in `cached_package_path` into the changelog index, so that later queries don't need to
+    /// open the packages again. Returns the number of packages that were found.
+    fn index_cached_changelogs(&self, show_progress: bool) -> Result<usize> {
+        let paths = self.get_cached_package_dirs()?;
+        let query = ChangelogQuery { show_progress, ..Default::default() };
+        let mut scan = DirScan::default();
+        self.scan_dir_changelog_results(&query, &paths, &mut scan)?;
+        if let Some(err) = scan.index_error {
+            return Err(err)
+        }
+
+        Ok(scan.package_count)
+    }
+
+    /// Gets the changelogs of the new versions of all available updates that match the `query`. Packages are downloaded
+    /// into a subdirectory of `cache_path` per version, so each version is only downloaded once.
+    fn get_pending_changelog_results(&self, query: &ChangelogQuery, cache_path: &Path) -> Result<Vec<PackageChangelogResult>> {
+        let updates = self.check_update(true)?
+            .items
+            .into_iter()
+            .filter(|item| {
+                // dnf lists updates as name.arch
+                utilities::matches_query(&item.name, query)
+                    || item.name.rsplit_once('.').is_some_and(|(name, _)| utilities::matches_query(name, query))
+            });
+
+        let mut results = Vec::new();
+        for item in updates {
+            let version = item.new_version.as_deref().unwrap_or("unknown");
+            let mut package_path = PathBuf::from(cache_path);
+            package_path.push(format!("{}@{}", item.name, version).replace(['/', ':'], "_"));
+
+            let is_cached = fs::read_dir(&package_path).is_ok_and(|mut entries| entries.next().is_some());
+            if !is_cached {
+                fs::create_dir_all(&package_path)?;
+                if let Err(err) = self.download_package(&item, &package_path) {
+                    // Don't keep an empty directory around, since it would be treated as cached
+                    let _ = fs::remove_dir_all(&package_path);
+                    return Err(err)
+                }
+            }
+
+            match self.get_dir_changelog_results(query, &package_path) {
+                Ok(package_results) => results.extend(package_results),
+                Err(Error::NoChangelogsInDirectory | Error::NoPackagesInDirectory | Error::NoMatchingPackages(_)) => (),
+                Err(err) => return Err(err)
+            }
+        }
+
+        if results.is_empty() {
+            Err(Error::NoChangelogsInDirectory)
         } else {
-            Err(Error::UnkownCachedPackagePath)
+            Ok(results)
         }
     }
 
+    /// Gets the changelogs of the single package file at `path` if it matches the `query`, without scanning a directory
+    /// or using the changelog cache
+    fn get_file_changelog_results(&self, query: &ChangelogQuery, path: &Path) -> Result<Vec<PackageChangelogResult>> {
+        let result = self.read_package_changelogs(path).map_err(|err| match err {
+            Error::IO(err) => Error::IO(err),
+            err => Error::InvalidPackageFile(path.display().to_string(), err.to_string())
+        })?;
+        let result = self.filter_package_changelogs(query, result)?;
+
+        if result.changelogs.is_empty() {
+            Err(Error::NoChangelogsInDirectory)
+        } else {
+            Ok(vec![result])
+        }
+    }
+
+    /// Gets the changelogs of the installed package `name` without any package files. Only the query's `since` filter
+    /// applies, since all entries belong to the installed version or older ones.
+    fn get_installed_changelog_results(&self, _query: &ChangelogQuery, _name: &str) -> Result<Vec<PackageChangelogResult>> {
+        Err(Error::Unsupported("reading the changelogs of installed packages"))
+    }
+
+    /// Downloads the new version of a package with an available update into `path`, without installing it
+    fn download_package(&self, _item: &PackageUpdateItem, _path: &Path) -> Result<()> {
+        Err(Error::Unsupported("downloading individual packages"))
+    }
+
     /// Within the given `path`, for all package names that match the `query`, recursively finds all changelogs
-    /// for each package, and appends them to a single output string.
-    fn get_dir_changelogs(&self, query: &ChangelogQuery, path: &Path) -> Result<String> {
-        let subpaths = fs::read_dir(path)?;
-        let changelogs = subpaths.map(|item| {
-            let entry = item?;
-            let file_type = entry.file_type()?;
-    
-            if file_type.is_dir() {
-                self.get_dir_changelogs(&query, entry.path().as_path())
-            } else {
-                self.get_package_changelogs_string(&query, entry.path().as_path())
+    /// for each package. Packages without any changelogs are left out. If no package matches the query's name,
+    /// then returns `Error::NoMatchingPackages` with the closest package names that were found. Otherwise, if no
+    /// package has any changelogs, then returns `Error::NoChangelogsInDirectory`, or `Error::NoPackagesInDirectory` if
+    /// there are no packages at all.
+    ///
+    /// Subdirectories that can't be read and packages that can't be parsed are skipped with a warning, but an error is
+    /// returned if `path` itself can't be read.
+    fn get_dir_changelog_results(&self, query: &ChangelogQuery, path: &Path) -> Result<Vec<PackageChangelogResult>> {
+        self.get_dirs_changelog_results(query, &[path.to_owned()])
+    }
+
+    /// Like `get_dir_changelog_results`, but across all directories in `paths`. A package that's in more than one of
+    /// them is only read from the copy that was modified last.
+    fn get_dirs_changelog_results(&self, query: &ChangelogQuery, paths: &[PathBuf]) -> Result<Vec<PackageChangelogResult>> {
+        let mut scan = DirScan::default();
+        let results = self.scan_dir_changelog_results(query, paths, &mut scan)?;
+
+        if !scan.skipped_paths.is_empty() {
+            eprintln!("Warning: skipped {} unreadable paths:", scan.skipped_paths.len());
+            for skipped_path in &scan.skipped_paths {
+                eprintln!("  {}", skipped_path.display());
             }
-        })
-        .filter(|result| result.is_ok())
-        .map(|result| result.unwrap())
-        .collect::<Vec<String>>();
-    
-        if changelogs.is_empty() {
+        }
+
+        if !scan.failed_packages.is_empty() {
+            eprintln!("Warning: skipped {} packages due to errors:", scan.failed_packages.len());
+            for (failed_path, error) in &scan.failed_packages {
+                eprintln!("  {}: {}", failed_path.display(), error);
+            }
+        }
+
+        // The index only saves time, so the changelogs are still shown if it can't be written
+        if let Some(ref err) = scan.index_error {
+            eprintln!("Warning: could not save the changelog index: {}", err);
+        }
+
+        if scan.package_count == 0 {
+            return Err(Error::NoPackagesInDirectory)
+        }
+
+        if let (true, Some(name)) = (results.is_empty(), &query.name) {
+            let suggestions = utilities::closest_names(name, &scan.unmatched_names, self.get_config().fuzzy_max_distance);
+            if !suggestions.is_empty() {
+                return Err(Error::NoMatchingPackages(suggestions))
+            }
+        }
+
+        let results = results.into_iter()
+            .filter(|result| !result.changelogs.is_empty())
+            .collect::<Vec<PackageChangelogResult>>();
+
+        if results.is_empty() {
             Err(Error::NoChangelogsInDirectory)
         } else {
-            let mut changelog_string = String::new();
-            for (i, changelog) in changelogs.iter().enumerate() {
-                if i > 0 {
-                    changelog_string.push_str("\n\n");
-                }
-                changelog_string.push_str(&changelog);
-            }
-        
-            Ok(changelog_string)
+            Ok(results)
         }
     }
 
-    /// Gets all changelogs for a package at the given path, filtering out any changelogs that
-    /// have a timestamp before the latest changelog of the corresponding installed package.
-    /// If query does not match the package name, then returns `Error::PackageNameDoesNotMatch`.
-    fn get_package_changelogs_string(&self, query: &ChangelogQuery, path: &Path) -> Result<String> {
-        let PackageChangelogResult { name, changelogs } = self.get_package_changelogs_result(query, path)?;
-    
-        if changelogs.is_empty() {
-            Err(Error::NoChangelogsForPackage)
-        } else {
-            let mut changelog_string = format!("==== {} ====", name);
-            for changelog in changelogs {
-                changelog_string.push_str("\n");
-                changelog_string.push_str(&changelog);
+    /// Recursively gets the changelog results of all packages in `paths` that match the `query`, including those without
+    /// any changelogs. Returns an error only if none of `paths` can be read, while anything unreadable is recorded in
+    /// `scan` along with the names of the packages that don't match. Packages are read on up to `scan_threads` threads
+    /// from settings.
+    fn scan_dir_changelog_results(&self, query: &ChangelogQuery, paths: &[PathBuf], scan: &mut DirScan) -> Result<Vec<PackageChangelogResult>> {
+        let files = find_scan_files_in_dirs(paths, self.package_extensions(), scan)?;
+        scan.package_count = files.len();
+        let thread_count = utilities::get_scan_thread_count(self.get_config());
+        let chunk_size = files.len().div_ceil(thread_count).max(1);
+        let cache = Mutex::new(if query.skip_index { ChangelogCache::default() } else { ChangelogCache::load() });
+        let scanned_count = AtomicUsize::new(0);
+        let matched_count = AtomicUsize::new(0);
+        let file_count = files.len();
+
+        let file_results = thread::scope(|s| {
+            let (cache, scanned_count, matched_count) = (&cache, &scanned_count, &matched_count);
+            let threads = files.chunks(chunk_size)
+                .map(|chunk| s.spawn(move || {
+                    chunk.iter()
+                        .map(|file| {
+                            let result = self.get_cached_package_changelogs_result(query, file, cache);
+                            if query.show_progress {
+                                let scanned = scanned_count.fetch_add(1, Ordering::Relaxed) + 1;
+                                let matched = if result.is_ok() {
+                                    matched_count.fetch_add(1, Ordering::Relaxed) + 1
+                                } else {
+                                    matched_count.load(Ordering::Relaxed)
+                                };
+                                eprint!("\rScanning packages: {}/{} ({}%), {} matched", scanned, file_count,
+                                    scanned * 100 / file_count, matched);
+                            }
+                            (file, result)
+                        })
+                        .collect::<Vec<_>>()
+                }))
+                .collect::<Vec<_>>();
+
+            threads.into_iter()
+                // A panicking worker would otherwise leave its packages out of the results without a trace
+                .flat_map(|thread| thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect::<Vec<_>>()
+        });
+
+        if query.show_progress && !files.is_empty() {
+            // Clears the progress line, so that it doesn't run into warnings printed after the scan
+            eprint!("\r\x1b[2K");
+        }
+
+        let mut results = Vec::new();
+        for (file, result) in file_results {
+            match result {
+                Ok(result) => results.push(result),
+                Err(Error::PackageNameDoesNotMatch(name)) => { scan.unmatched_names.insert(name); },
+                Err(Error::IO(_)) => scan.skipped_paths.push(file.to_owned()),
+                Err(err) => scan.failed_packages.push((file.to_owned(), err.to_string()))
             }
-        
-            Ok(changelog_string)
         }
+
+        if let (false, Ok(cache)) = (query.skip_index, cache.into_inner()) {
+            scan.index_error = cache.save().err();
+        }
+
+        Ok(results)
+    }
+
+    /// Gets the changelogs of the package file at `path` that match the `query`, only reading the package if its
+    /// changelogs aren't in the `cache` yet
+    fn get_cached_package_changelogs_result(&self, query: &ChangelogQuery, path: &Path, cache: &Mutex<ChangelogCache>) -> Result<PackageChangelogResult> {
+        let cached_result = cache.lock().ok().and_then(|cache| cache.get(path));
+        let result = match cached_result {
+            Some(result) => result,
+            None => {
+                let result = self.read_package_changelogs(path)?;
+                if let Ok(mut cache) = cache.lock() {
+                    cache.insert(path, result.clone());
+                }
+                result
+            }
+        };
+
+        self.filter_package_changelogs(query, result)
     }
 
     fn get_config(&self) -> &PackageConfig;
 
+    /// Lists the names of all installed packages
+    fn installed_packages(&self) -> Result<HashSet<String>> {
+        Err(Error::Unsupported("listing installed packages"))
+    }
+
+    /// Checks the signatures and digests of every package in `cached_package_path`
+    fn verify_cache(&self) -> Result<Vec<PackageVerificationResult>> {
+        Err(Error::Unsupported("verifying cached packages"))
+    }
+
+    /// File extensions of the package manager's packages, without the leading dot, which may contain `*` wildcards
+    /// for variants such as compression formats
+    fn package_extensions(&self) -> &[&str];
+
     /// Uses package manager specific logic to open the package file at the given path, and returns the package name
-    /// along with a list of changelog entries.
-    fn get_package_changelogs_result(&self, query: &ChangelogQuery, path: &Path) -> Result<PackageChangelogResult>;
+    /// along with all of its changelog entries.
+    fn read_package_changelogs(&self, path: &Path) -> Result<PackageChangelogResult>;
+
+    /// Checks that the package matches the `query`, and leaves out the changelog entries of the installed version
+    /// and older ones. Returns `Error::PackageNameDoesNotMatch` if it doesn't match.
+    fn filter_package_changelogs(&self, query: &ChangelogQuery, result: PackageChangelogResult) -> Result<PackageChangelogResult>;
+
+    /// Lists the available updates. If `refresh` is false, the repositories aren't refreshed first, and the
+    /// cached metadata is used instead.
+    fn check_update(&self, refresh: bool) -> Result<UpdateSummary>;
+
+    /// Runs the download command from settings. If `download_dir` is provided, the packages are downloaded there
+    /// instead of the package manager's cache. If `on_progress` is provided, it's called with each line the command
+    /// prints as the download proceeds.
+    fn download_update(&self, download_dir: Option<&Path>, elevate_privileges: bool, on_progress: Option<&mut dyn FnMut(&str)>) -> Result<()> {
+        let config = self.get_config();
+        let command = match download_dir {
+            Some(download_dir) => self.get_download_dir_command(&config.download_command, download_dir)
+                .ok_or(Error::Unsupported("downloading to a directory"))?,
+            None => config.download_command.clone()
+        };
+
+        match on_progress {
+            Some(on_progress) => utilities::run_streaming_shell_command(config, command.as_str(),
+                elevate_privileges, on_progress, Some(Error::download_error)),
+            None => utilities::run_shell_command(config, command.as_str(), elevate_privileges,
+                Some(Error::download_error))
+        }
+    }
+
+    /// Changes the download `command` to put the packages in `download_dir`, or returns `None` if that isn't
+    /// possible, e.g. if the command doesn't run the package manager directly
+    fn get_download_dir_command(&self, _command: &str, _download_dir: &Path) -> Option<String> {
+        None
+    }
+
+    fn do_update(&self, options: &UpdateOptions, interactive: bool, elevate_privileges: bool) -> Result<()> {
+        let command = self.get_selected_update_command(options, interactive);
+        self.run_update_command(&command, options, interactive, elevate_privileges)
+    }
+
+    /// Gets the command that `do_update` starts from, which is the package manager's command for updating individual
+    /// packages if `options` names any, or otherwise the update command from settings
+    fn get_selected_update_command(&self, options: &UpdateOptions, interactive: bool) -> String {
+        match self.get_package_update_command(interactive) {
+            Some(command) if !options.packages.is_empty() => command,
+            _ => self.get_base_update_command(false, interactive)
+        }
+    }
+
+    /// The command that updates only the packages whose names are appended to it, or `None` if they're appended to the
+    /// update command from settings instead
+    fn get_package_update_command(&self, _interactive: bool) -> Option<String> {
+        None
+    }
+
+    /// Runs a distribution upgrade, which unlike a regular update may change vendors, downgrade or remove packages
+    /// in order to move to a new release. Package managers that don't make this distinction run a regular update.
+    fn dist_upgrade(&self, options: &UpdateOptions, interactive: bool, elevate_privileges: bool) -> Result<()> {
+        let command = self.get_base_update_command(true, interactive);
+        self.run_update_command(&command, options, interactive, elevate_privileges)
+    }
+
+    /// Installs only the updates that fix security issues. Exclusions are only supported if the package manager
+    /// can exclude packages natively, since the command can't be restricted to a list of packages instead.
+    fn do_security_update(&self, options: &UpdateOptions, interactive: bool, elevate_privileges: bool) -> Result<()> {
+        let config = self.get_config();
+        let command = self.get_security_update_command(interactive)
+            .ok_or(Error::Unsupported("security-only updates"))?;
+
+        let has_exclusions = !options.exclude.is_empty() || !config.ignored_packages.is_empty();
+        if has_exclusions && self.get_exclude_args(&[]).is_none() {
+            return Err(Error::Unsupported("excluding packages from a security-only update"))
+        }
+
+        self.run_update_command(&command, options, interactive, elevate_privileges)
+    }
 
-    fn check_update(&self) -> Result<Vec<PackageUpdateItem>>;
+    /// The command that installs only security updates, or `None` if the package manager can't filter updates that way
+    fn get_security_update_command(&self, _interactive: bool) -> Option<String> {
+        None
+    }
+
+    /// Gets the update command from settings, or the distribution upgrade command if `dist` is set. The latter
+    /// falls back to the package manager's default, and then to the regular update command.
+    fn get_base_update_command(&self, dist: bool, interactive: bool) -> String {
+        let config = self.get_config();
+        let (update_command, dist_upgrade_command) = if interactive {
+            (&config.update_command, &config.dist_upgrade_command)
+        } else {
+            (&config.noconfirm_update_command, &config.noconfirm_dist_upgrade_command)
+        };
+
+        if dist {
+            dist_upgrade_command.clone()
+                .or_else(|| self.get_default_dist_upgrade_command(interactive))
+                .unwrap_or_else(|| update_command.clone())
+        } else {
+            update_command.clone()
+        }
+    }
+
+    /// The distribution upgrade command to use if none is provided in settings, or `None` if the package manager
+    /// doesn't distinguish it from a regular update.
+    fn get_default_dist_upgrade_command(&self, _interactive: bool) -> Option<String> {
+        None
+    }
+
+    /// Runs the update (or distribution upgrade if `dist` is set) in a mode that declines the final confirmation,
+    /// and returns the proposed transaction along with any conflicts.
+    fn preview_update(&self, options: &UpdateOptions, dist: bool, elevate_privileges: bool) -> Result<UpdatePreview> {
+        let config = self.get_config();
+        let interactive_command = self.get_base_update_command(dist, true);
+        let noconfirm_command = self.get_base_update_command(dist, false);
+        if interactive_command.is_empty() || noconfirm_command.is_empty() {
+            return Err(Error::EmptyCommand)
+        }
 
-    fn download_update(&self, elevate_privileges: bool) -> Result<()> {
+        let preview_command = self.get_preview_command(&interactive_command, &noconfirm_command)
+            .ok_or(Error::Unsupported("previewing an update"))?;
+        let command = self.get_update_command(&preview_command, options)?;
+        let transaction = utilities::capture_shell_command(config, &command, elevate_privileges)?;
+        let conflicts = self.parse_conflicts(&transaction);
+
+        Ok(UpdatePreview { transaction, conflicts })
+    }
+
+    /// Builds a command from the configured update commands that prints the transaction without applying it,
+    /// or returns `None` if the package manager has no such mode.
+    fn get_preview_command(&self, _interactive_command: &str, _noconfirm_command: &str) -> Option<String> {
+        None
+    }
+
+    /// Finds the lines of the package manager's output that describe conflicts or other problems
+    fn parse_conflicts(&self, output: &str) -> Vec<String> {
+        output.lines()
+            .map(|line| line.trim())
+            .filter(|line| {
+                let lowercase = line.to_lowercase();
+                lowercase.starts_with("problem") || lowercase.starts_with("error") || lowercase.contains("conflict")
+            })
+            .map(String::from)
+            .collect()
+    }
+
+    /// Downloads the available updates and schedules them to be installed on the next reboot, so that libraries aren't
+    /// replaced while they're in use. Excluding packages isn't supported for offline updates.
+    fn offline_update(&self, options: &UpdateOptions, elevate_privileges: bool) -> Result<()> {
         let config = self.get_config();
-        utilities::run_shell_command(config.download_command.as_str(), elevate_privileges, 
-            Some(|err| Error::DownloadError(err)))
+        if !options.exclude.is_empty() || !config.ignored_packages.is_empty() {
+            return Err(Error::Unsupported("excluding packages from an offline update"))
+        }
+
+        match config.offline_update_command.clone().or_else(|| self.get_default_offline_update_command()) {
+            Some(command) => utilities::run_shell_command(config, &command, elevate_privileges, Some(Error::update_error)),
+            None => Err(Error::Unsupported("offline updates"))
+        }
     }
 
-    fn do_update(&self, interactive: bool, elevate_privileges: bool) -> Result<()> {
+    /// The offline update command to use if none is provided in settings, or `None` if the package manager
+    /// doesn't support offline updates.
+    fn get_default_offline_update_command(&self) -> Option<String> {
+        None
+    }
+
+    fn run_update_command(&self, command: &str, options: &UpdateOptions, interactive: bool, elevate_privileges: bool) -> Result<()> {
         let config = self.get_config();
+        let command = self.build_update_command(command, options, interactive)?;
+
         if interactive {
-            utilities::run_interactive_shell_command(config.update_command.as_str(), elevate_privileges)
+            utilities::run_interactive_shell_command(config, command.as_str(), elevate_privileges, Some(Error::update_error))
         } else {
-            utilities::run_shell_command(config.noconfirm_update_command.as_str(), elevate_privileges,
-                Some(|err| Error::UpdateError(err)))
+            utilities::run_shell_command(config, command.as_str(), elevate_privileges, Some(Error::update_error))
+        }
+    }
+
+    /// Applies the `options` and settings to the update `command`, giving the command that `run_update_command` runs
+    fn build_update_command(&self, command: &str, options: &UpdateOptions, interactive: bool) -> Result<String> {
+        let config = self.get_config();
+        let command = match self.get_reboot_patches_command(command) {
+            Some(reboot_patches_command) if !interactive && config.include_reboot_patches => reboot_patches_command,
+            _ => String::from(command)
+        };
+        let command = self.get_update_command(&command, options)?;
+
+        if options.fail_on_conflict && !interactive {
+            self.get_fail_on_conflict_command(&command).ok_or(Error::Unsupported("failing on conflicts"))
+        } else {
+            Ok(command)
+        }
+    }
+
+    /// Modifies a non-interactive update `command` so that it also installs patches that require a reboot, which
+    /// would otherwise be skipped, or returns `None` if the package manager doesn't skip them
+    fn get_reboot_patches_command(&self, _command: &str) -> Option<String> {
+        None
+    }
+
+    /// Modifies a non-interactive update `command` so that it fails on conflicts that could only be resolved by changing
+    /// vendors, downgrading or removing packages, or returns `None` if the package manager can't be told to
+    fn get_fail_on_conflict_command(&self, _command: &str) -> Option<String> {
+        None
+    }
+
+    /// Appends any excluded packages to the configured update `command`. If the package manager can't exclude
+    /// packages natively, the command is instead restricted to the available updates that aren't excluded. If
+    /// `options` names the packages to update, the command is restricted to those that aren't excluded.
+    fn get_update_command(&self, command: &str, options: &UpdateOptions) -> Result<String> {
+        if command.is_empty() {
+            return Err(Error::EmptyCommand)
+        }
+
+        let command = if options.include_optional {
+            let optional_args = self.get_include_optional_args()
+                .ok_or(Error::Unsupported("including optional updates"))?;
+            format!("{} {}", command, optional_args)
+        } else {
+            String::from(command)
+        };
+        let command = command.as_str();
+
+        let patterns = self.get_config().ignored_packages.iter()
+            .chain(options.exclude.iter())
+            .cloned()
+            .collect::<Vec<String>>();
+
+        if !options.packages.is_empty() {
+            let packages = options.packages.iter()
+                .filter(|name| !patterns.iter().any(|pattern| utilities::matches_pattern(name, pattern)))
+                .map(|name| utilities::quote_shell_arg(name))
+                .collect::<Vec<String>>();
+
+            return if packages.is_empty() {
+                Err(Error::AllUpdatesExcluded)
+            } else {
+                Ok(format!("{} {}", command, packages.join(" ")))
+            }
+        }
+
+        if patterns.is_empty() {
+            return Ok(String::from(command))
+        }
+
+        if let Some(exclude_args) = self.get_exclude_args(&patterns) {
+            return Ok(format!("{} {}", command, exclude_args))
+        }
+
+        let packages = self.check_update(true)?
+            .items
+            .into_iter()
+            .filter(|item| !patterns.iter().any(|pattern| utilities::matches_pattern(&item.name, pattern)))
+            .map(|item| utilities::quote_shell_arg(&item.name))
+            .collect::<Vec<String>>();
+
+        if packages.is_empty() {
+            Err(Error::AllUpdatesExcluded)
+        } else {
+            Ok(format!("{} {}", command, packages.join(" ")))
+        }
+    }
+
+    /// Returns the arguments that make the update include what the package manager holds back by default, or `None`
+    /// if it can't be told to
+    fn get_include_optional_args(&self) -> Option<String> {
+        None
+    }
+
+    /// Returns the arguments that make the update command skip packages matching `patterns`, or `None` if the
+    /// package manager has no native way of excluding packages.
+    fn get_exclude_args(&self, _patterns: &[String]) -> Option<String> {
+        None
+    }
+
+    /// Checks whether the system needs to be rebooted to finish applying updates. Returns `None` if
+    /// the package manager can't tell.
+    fn reboot_required(&self) -> Option<bool> {
+        None
+    }
+
+    /// Whether updating `items` requires a reboot according to `reboot_triggers` in settings. Used as a fallback
+    /// when `reboot_required` can't tell.
+    fn reboot_triggered_by(&self, items: &[PackageUpdateItem]) -> bool {
+        let triggers = &self.get_config().reboot_triggers;
+        items.iter().any(|item| triggers.iter().any(|pattern| utilities::matches_pattern(item.base_name(), pattern)))
+    }
+
+    /// Lists the running services that still use libraries replaced by an update. Returns
+    /// `Error::Unsupported` if the package manager can't tell, as opposed to an empty list.
+    fn services_needing_restart(&self) -> Result<Vec<String>> {
+        Err(Error::Unsupported("listing services that need restarting"))
+    }
+
+    /// Creates a snapshot to restore if an update goes wrong, using `snapshot_command` from settings or snapper by default,
+    /// and returns the snapshot number if the command printed one. Returns `Error::Unsupported` if there's no snapshot
+    /// command in settings and snapper isn't installed.
+    fn create_snapshot(&self, elevate_privileges: bool) -> Result<Option<u32>> {
+        let config = self.get_config();
+        let command = match config.snapshot_command {
+            Some(ref command) => command.as_str(),
+            None if utilities::tool_version("snapper").is_some() => utilities::SNAPPER_SNAPSHOT_COMMAND,
+            None => return Err(Error::Unsupported("creating snapshots without snapper"))
+        };
+        let stdout = utilities::output_shell_command(config, command, elevate_privileges, Some(Error::SnapshotError))?;
+
+        Ok(stdout.lines().last().and_then(|line| line.trim().parse::<u32>().ok()))
+    }
+
+    /// Lists the configured repositories, including disabled ones
+    fn list_repositories(&self) -> Result<Vec<RepoInfo>> {
+        Err(Error::Unsupported("listing repositories"))
+    }
+
+    /// Enables or disables the repository whose id or name is `name`. Returns `Error::NoSuchRepository` if there's no
+    /// such repository.
+    fn set_repository_enabled(&self, name: &str, enabled: bool, elevate_privileges: bool) -> Result<()> {
+        let repo = self.list_repositories()?
+            .into_iter()
+            .find(|repo| repo.id == name || repo.name == name)
+            .ok_or_else(|| Error::NoSuchRepository(name.to_owned()))?;
+        let command = self.get_repository_enabled_command(&repo.id, enabled)
+            .ok_or(Error::Unsupported("enabling and disabling repositories"))?;
+
+        utilities::run_shell_command(self.get_config(), &command, elevate_privileges, Some(Error::RepositoryError))
+    }
+
+    /// The command that enables or disables the repository with the given `id`, or `None` if the package manager
+    /// can't toggle repositories
+    fn get_repository_enabled_command(&self, _id: &str, _enabled: bool) -> Option<String> {
+        None
+    }
+
+    /// Lists the patches that are needed by the system
+    fn list_patches(&self) -> Result<Vec<PatchItem>> {
+        Err(Error::Unsupported("patches"))
+    }
+
+    /// Installs a single patch by name
+    fn apply_patch(&self, _name: &str, _interactive: bool, _elevate_privileges: bool) -> Result<()> {
+        Err(Error::Unsupported("patches"))
+    }
+
+    /// Runs a hook command from settings, such as `pre_update_command`. Failures are returned as `Error::HookError`,
+    /// so that they can be told apart from a failed update.
+    fn run_hook(&self, command: &str, elevate_privileges: bool) -> Result<()> {
+        utilities::run_shell_command(self.get_config(), command, elevate_privileges, Some(Error::HookError))
+    }
+
+    fn restart_services(&self, services: &[String], elevate_privileges: bool) -> Result<()> {
+        let config = self.get_config();
+        let service_args = services.iter()
+            .map(|service| utilities::quote_shell_arg(service))
+            .collect::<Vec<String>>();
+        let command = format!("systemctl restart {}", service_args.join(" "));
+
+        utilities::run_shell_command(config, command.as_str(), elevate_privileges, Some(Error::ServiceRestartError))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::storage::Config;
+
+    use super::*;
+
+    fn result(name: &str, description: &str) -> PackageChangelogResult {
+        PackageChangelogResult {
+            name: String::from(name),
+            changelogs: vec![ChangelogEntry { timestamp: 1704110400, author: None, description: String::from(description) }]
+        }
+    }
+
+    /// A directory tree for one test, which is removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("package-assistant-scan-test-{}-{}", std::process::id(), name));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn create_file(&self, relative_path: &str) -> PathBuf {
+            let path = self.0.join(relative_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn scan_lists_duplicate_packages_once_from_newest_directory() {
+        let set_modified = |path: &Path, secs: u64| fs::File::options().write(true).open(path).unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            .unwrap();
+        let main_dir = TestDir::new("dedup-main");
+        let manual_dir = TestDir::new("dedup-manual");
+        let old_vim = main_dir.create_file("x86_64/vim.rpm");
+        set_modified(&old_vim, 1704110400);
+        let git = main_dir.create_file("x86_64/git.rpm");
+        let new_vim = manual_dir.create_file("vim.rpm");
+        set_modified(&new_vim, 1704888000);
+
+        let paths = [main_dir.0.clone(), manual_dir.0.clone()];
+        let files = find_scan_files_in_dirs(&paths, &["rpm"], &mut DirScan::default()).unwrap();
+        assert_eq!(files, [git.clone(), new_vim]);
+
+        // Copies in subdirectories of a single path are also only listed once
+        let copied_vim = main_dir.create_file("noarch/vim.rpm");
+        set_modified(&copied_vim, 1701432000);
+        let files = find_scan_files_in_dirs(std::slice::from_ref(&main_dir.0), &["rpm"], &mut DirScan::default()).unwrap();
+        assert_eq!(files, [git, old_vim]);
+    }
+
+    #[test]
+    fn scan_skips_unreadable_subdirectories() {
+        let dir = TestDir::new("unreadable");
+        let readable_file = dir.create_file("a/vim.rpm");
+        dir.create_file("b/git.rpm");
+        let unreadable_dir = dir.0.join("b");
+        fs::set_permissions(&unreadable_dir, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let mut scan = DirScan::default();
+        let files = find_scan_files(&dir.0, &["rpm"], &mut scan);
+        fs::set_permissions(&unreadable_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let files = files.unwrap();
+        assert!(files.contains(&readable_file));
+        // Root can read the directory regardless of its permissions
+        if !nix::unistd::geteuid().is_root() {
+            assert_eq!(files, [readable_file]);
+            assert_eq!(scan.skipped_paths, [unreadable_dir]);
+        }
+    }
+
+    #[test]
+    fn scan_of_missing_directory_fails() {
+        let dir = TestDir::new("missing");
+        let mut scan = DirScan::default();
+        let result = find_scan_files(&dir.0.join("missing"), &["rpm"], &mut scan);
+        assert!(matches!(result, Err(Error::DirectoryNotFound(_))));
+    }
+
+    /// Reads every package as a single changelog entry, counting how many packages are read in total and at the same time
+    struct CountingManager {
+        config: PackageConfig,
+        read_count: AtomicUsize,
+        running: AtomicUsize,
+        max_running: AtomicUsize
+    }
+
+    impl CountingManager {
+        fn new(config: PackageConfig) -> Self {
+            Self { config, read_count: AtomicUsize::new(0), running: AtomicUsize::new(0), max_running: AtomicUsize::new(0) }
         }
     }
-}
\ No newline at end of file
+
+    impl PackageManager for CountingManager {
+        fn get_config(&self) -> &PackageConfig {
+            &self.config
+        }
+
+        fn package_extensions(&self) -> &[&str] {
+            &["rpm"]
+        }
+
+        fn read_package_changelogs(&self, path: &Path) -> Result<PackageChangelogResult> {
+            self.read_count.fetch_add(1, Ordering::SeqCst);
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let changelog = ChangelogEntry { timestamp: 1704110400, author: None, description: String::from("- Update") };
+            Ok(PackageChangelogResult { name, changelogs: vec![changelog] })
+        }
+
+        fn filter_package_changelogs(&self, _query: &ChangelogQuery, result: PackageChangelogResult) -> Result<PackageChangelogResult> {
+            Ok(result)
+        }
+
+        fn check_update(&self, _refresh: bool) -> Result<UpdateSummary> {
+            Err(Error::Unsupported("checking for updates"))
+        }
+    }
+
+    #[test]
+    fn empty_missing_and_populated_directories_are_distinguished() {
+        let manager = CountingManager::new(Config::default().package);
+        let query = ChangelogQuery { skip_index: true, ..Default::default() };
+        let dir = TestDir::new("empty");
+        dir.create_file("README");
+
+        let empty = manager.get_dir_changelog_results(&query, &dir.0);
+        assert!(matches!(empty, Err(Error::NoPackagesInDirectory)));
+
+        let missing = manager.get_dir_changelog_results(&query, &dir.0.join("missing"));
+        assert!(matches!(missing, Err(Error::DirectoryNotFound(_))));
+
+        dir.create_file("vim.rpm");
+        let populated = manager.get_dir_changelog_results(&query, &dir.0).unwrap();
+        assert_eq!(populated.iter().map(|result| result.name.as_str()).collect::<Vec<&str>>(), ["vim"]);
+    }
+
+    #[test]
+    fn scan_reads_packages_on_at_most_scan_threads_threads() {
+        let dir = TestDir::new("threads");
+        for i in 0..12 {
+            dir.create_file(&format!("package-{}.rpm", i));
+        }
+
+        let mut config = Config::default().package;
+        config.scan_threads = Some(3);
+        let manager = CountingManager::new(config);
+        let query = ChangelogQuery { skip_index: true, ..Default::default() };
+
+        let results = manager.scan_dir_changelog_results(&query, std::slice::from_ref(&dir.0), &mut DirScan::default()).unwrap();
+        assert_eq!(results.len(), 12);
+        let max_running = manager.max_running.load(Ordering::SeqCst);
+        assert!((1..=3).contains(&max_running), "{} packages were read at the same time", max_running);
+    }
+
+    #[test]
+    fn unchanged_package_is_read_from_the_index() {
+        let dir = TestDir::new("index");
+        let path = dir.create_file("vim.rpm");
+        let manager = CountingManager::new(Config::default().package);
+        let query = ChangelogQuery::default();
+        let cache = Mutex::new(ChangelogCache::default());
+
+        manager.get_cached_package_changelogs_result(&query, &path, &cache).unwrap();
+        manager.get_cached_package_changelogs_result(&query, &path, &cache).unwrap();
+        assert_eq!(manager.read_count.load(Ordering::SeqCst), 1);
+
+        fs::write(&path, "changed").unwrap();
+        manager.get_cached_package_changelogs_result(&query, &path, &cache).unwrap();
+        assert_eq!(manager.read_count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn updates_are_sorted_by_name_ignoring_case() {
+        let update = |name: &str, category: Option<&str>| PackageUpdateItem {
+            name: String::from(name),
+            old_version: None,
+            new_version: None,
+            category: category.map(String::from),
+            arch: None
+        };
+        let summary = UpdateSummary::new(vec![
+            update("vim", None),
+            update("NetworkManager", Some("security")),
+            update("curl", Some("security")),
+            update("Mesa", None),
+            update("libzypp", None),
+            update("mesa", None)
+        ]);
+
+        let names = summary.items.iter().map(|item| item.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, ["curl", "libzypp", "Mesa", "mesa", "NetworkManager", "vim"]);
+        assert_eq!((summary.total, summary.security_count), (6, 2));
+    }
+
+    #[test]
+    fn cache_is_cleaned_by_age_and_keeps_latest_versions() {
+        let dir = TestDir::new("clean");
+        let now = SystemTime::now();
+        let set_modified = |path: &Path, age_days: u64| fs::File::options().write(true).open(path).unwrap()
+            .set_modified(now - Duration::from_secs(age_days * 24 * 60 * 60))
+            .unwrap();
+        let create_package = |file_name: &str, age_days: u64| {
+            let path = dir.create_file(file_name);
+            fs::write(&path, file_name).unwrap();
+            set_modified(&path, age_days);
+            path
+        };
+
+        let old_vim = create_package("vim-9.0-1.x86_64.rpm", 30);
+        let newer_vim = create_package("vim-9.1-1.x86_64.rpm", 20);
+        let recent_vim = create_package("vim-9.1-2.x86_64.rpm", 1);
+        let old_curl = create_package("curl-8.5-1.x86_64.rpm", 40);
+
+        let mut config = Config::default().package;
+        config.cached_package_path = Some(crate::storage::PathList::Single(dir.0.clone()));
+        let manager = CountingManager::new(config);
+
+        // Only the older versions of vim go, while its recent version and the only version of curl are kept
+        let cleanup = manager.clean_cache(Some(Duration::from_secs(7 * 24 * 60 * 60)), true).unwrap();
+        assert_eq!(cleanup.removed_count, 2);
+        assert_eq!(cleanup.freed_bytes, (old_vim.file_name().unwrap().len() + newer_vim.file_name().unwrap().len()) as u64);
+        assert!(cleanup.failed.is_empty());
+        assert!(!old_vim.exists() && !newer_vim.exists());
+        assert!(recent_vim.exists() && old_curl.exists());
+
+        // Without `--keep-latest`, only the recent version is newer than the cutoff
+        let cleanup = manager.clean_cache(Some(Duration::from_secs(7 * 24 * 60 * 60)), false).unwrap();
+        assert_eq!(cleanup.removed_count, 1);
+        assert!(recent_vim.exists() && !old_curl.exists());
+
+        let cleanup = manager.clean_cache(None, false).unwrap();
+        assert_eq!(cleanup.removed_count, 1);
+        assert!(!recent_vim.exists());
+    }
+
+    #[test]
+    fn grep_keeps_entries_matching_substring_or_regex() {
+        let results = || vec![
+            PackageChangelogResult {
+                name: String::from("openssl"),
+                changelogs: vec![
+                    ChangelogEntry { timestamp: 1704110400, author: None, description: String::from("- Fix CVE-2024-0727") },
+                    ChangelogEntry { timestamp: 1704024000, author: None, description: String::from("- Update to 3.1.4") }
+                ]
+            },
+            result("curl", "- Fix CVE-2023-46218"),
+            result("vim", "- Update to 9.1")
+        ];
+        let names_and_descriptions = |results: &[PackageChangelogResult]| results.iter()
+            .flat_map(|result| result.changelogs.iter().map(|entry| (result.name.clone(), entry.description.clone())))
+            .collect::<Vec<(String, String)>>();
+
+        let substring = grep_changelog_results(results(), &Regex::new("CVE-2024").unwrap());
+        assert_eq!(names_and_descriptions(&substring), [(String::from("openssl"), String::from("- Fix CVE-2024-0727"))]);
+
+        let regex = grep_changelog_results(results(), &Regex::new(r"CVE-\d{4}-\d+").unwrap());
+        assert_eq!(regex.iter().map(|result| result.name.as_str()).collect::<Vec<&str>>(), ["openssl", "curl"]);
+        assert_eq!(regex[0].changelogs.len(), 1);
+
+        assert!(grep_changelog_results(results(), &Regex::new("CVE-2022").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn changelogs_start_without_blank_lines() {
+        let mut config = Config::default().package;
+        config.changelog_date_format = String::from("%Y-%m-%d");
+        config.changelog_timezone = Some(String::from("UTC"));
+
+        let mut vim = result("vim", "- Update to 9.1");
+        vim.changelogs[0].author = Some(String::from("Jane Doe <jane@example.com> - 9.1"));
+        let output = format_changelog_results(&config, &[vim, result("git", "- Fix")]);
+        assert!(!output.starts_with('\n'));
+        assert_eq!(output, "==== vim ====\n\
+            * 2024-01-01 Jane Doe <jane@example.com> - 9.1\n\
+            - Update to 9.1\n\
+            \n\
+            ==== git ====\n\
+            * 2024-01-01\n\
+            - Fix");
+    }
+
+    #[test]
+    fn entries_use_custom_date_format() {
+        let mut config = Config::default().package;
+        config.changelog_date_format = String::from("%d.%m.%Y %H:%M");
+        config.changelog_timezone = Some(String::from("UTC"));
+
+        let output = format_changelog_results(&config, &[result("vim", "- Update")]);
+        assert!(output.contains("* 01.01.2024 12:00\n- Update"));
+    }
+
+    #[test]
+    fn invalid_date_format_falls_back_to_default() {
+        let mut config = Config::default().package;
+        config.changelog_date_format = String::from("%Y-%Q");
+        assert_eq!(get_changelog_date_format(&config), DEFAULT_CHANGELOG_DATE_FORMAT);
+
+        config.changelog_timezone = Some(String::from("UTC"));
+        let output = format_changelog_results(&config, &[result("vim", "- Update")]);
+        assert!(output.contains("* Mon Jan 01 2024\n- Update"));
+    }
+
+    #[test]
+    fn timestamps_near_midnight_fall_on_the_zone_date() {
+        // 2024-01-01 23:30 UTC
+        let timestamp = 1704151800;
+        let utc = ChangelogTimezone::parse("utc").unwrap();
+        let ahead = ChangelogTimezone::parse("+02:00").unwrap();
+        let behind = ChangelogTimezone::parse("-05:00").unwrap();
+
+        assert_eq!(utc.format_timestamp(timestamp, "%Y-%m-%d %H:%M"), "2024-01-01 23:30");
+        assert_eq!(ahead.format_timestamp(timestamp, "%Y-%m-%d %H:%M"), "2024-01-02 01:30");
+        assert_eq!(behind.format_timestamp(timestamp, "%Y-%m-%d %H:%M"), "2024-01-01 18:30");
+    }
+
+    #[test]
+    fn invalid_timezone_isnt_parsed() {
+        assert!(ChangelogTimezone::parse("Europe/Berlin").is_none());
+        assert!(ChangelogTimezone::parse("+25:00").is_none());
+        assert!(matches!(ChangelogTimezone::parse("LOCAL"), Some(ChangelogTimezone::Local)));
+    }
+
+    #[test]
+    fn changelogs_use_custom_header_and_separator() {
+        let mut config = Config::default().package;
+        config.changelog_header_format = String::from("## {name} ({name}) ##");
+        config.changelog_separator = String::from("\n---\n");
+        config.changelog_date_format = String::from("%Y-%m-%d");
+        config.changelog_timezone = Some(String::from("UTC"));
+
+        let output = format_changelog_results(&config, &[result("vim", "- Update"), result("git", "- Fix")]);
+        assert!(output.starts_with("## vim (vim) ##\n"));
+        assert_eq!(output.matches("\n---\n## git (git) ##\n").count(), 1);
+        assert!(output.contains("2024-01-01"));
+    }
+
+    #[test]
+    fn empty_header_format_leaves_out_header() {
+        let mut config = Config::default().package;
+        config.changelog_header_format = String::new();
+        config.changelog_timezone = Some(String::from("UTC"));
+
+        let output = format_changelog_results(&config, &[result("vim", "- Update")]);
+        assert!(!output.contains("vim"));
+        assert!(output.contains("- Update"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use crate::storage::{PackageConfig, PackageManagerType};
+
+use super::{dnf, zypper, Error, PackageManager};
+use super::error::Result;
+
+/// Overrides `package_manager` in settings, e.g. for testing another backend in a container
+const PACKAGE_MANAGER_ENV_VAR: &str = "PACKAGE_ASSISTANT_MANAGER";
+
+type ManagerConstructor = for<'a> fn(&'a PackageConfig) -> Box<dyn PackageManager + 'a>;
+
+/// Maps each supported `package_manager` from settings to the constructor of its backend. A new backend
+/// only needs to be added here.
+fn get_registry() -> HashMap<PackageManagerType, ManagerConstructor> {
+    HashMap::from([
+        (PackageManagerType::Zypper, zypper::create_manager as ManagerConstructor),
+        (PackageManagerType::Dnf, dnf::create_manager as ManagerConstructor)
+    ])
+}
+
+/// Creates the backend for the package manager in the `PACKAGE_ASSISTANT_MANAGER` environment variable if it's set,
+/// or otherwise for `package_manager` in settings. Returns `Error::UnsupportedPackageManager` if neither is set or
+/// the package manager has no backend.
+pub fn get_package_manager<'a>(config: &'a PackageConfig) -> Result<Box<dyn PackageManager + 'a>> {
+    let env_value = std::env::var(PACKAGE_MANAGER_ENV_VAR).ok();
+    let package_manager_type = resolve_package_manager_type(config, env_value.as_deref())?;
+
+    get_registry().get(&package_manager_type)
+        .map(|create_manager| create_manager(config))
+        .ok_or(Error::UnsupportedPackageManager)
+}
+
+/// Chooses the package manager from the value of the `PACKAGE_ASSISTANT_MANAGER` environment variable, unless it's
+/// unset or empty, and otherwise from `package_manager` in settings
+fn resolve_package_manager_type(config: &PackageConfig, env_value: Option<&str>) -> Result<PackageManagerType> {
+    match env_value {
+        Some(value) if !value.is_empty() => value.parse::<PackageManagerType>()
+            .map_err(|message| Error::InvalidPackageManager(PACKAGE_MANAGER_ENV_VAR, message)),
+        _ => config.package_manager.ok_or(Error::UnsupportedPackageManager)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::Config;
+
+    use super::*;
+
+    #[test]
+    fn every_package_manager_resolves_or_is_unsupported() {
+        let config = Config::default().package;
+        let registry = get_registry();
+
+        for package_manager in [PackageManagerType::Zypper, PackageManagerType::Dnf, PackageManagerType::Apt, PackageManagerType::Pacman] {
+            // Fails to compile when a package manager is added, so that this test is updated along with the registry
+            let supported = match package_manager {
+                PackageManagerType::Zypper | PackageManagerType::Dnf => true,
+                PackageManagerType::Apt | PackageManagerType::Pacman => false
+            };
+
+            let manager = registry.get(&package_manager).map(|create_manager| create_manager(&config));
+            assert_eq!(manager.is_some(), supported, "{}", package_manager.as_str());
+            if let Some(manager) = manager {
+                assert_eq!(manager.package_extensions(), ["rpm"]);
+            }
+        }
+    }
+
+    #[test]
+    fn environment_variable_takes_precedence_over_settings() {
+        let mut config = Config::default().package;
+        config.package_manager = Some(PackageManagerType::Zypper);
+
+        assert!(matches!(resolve_package_manager_type(&config, Some("dnf")), Ok(PackageManagerType::Dnf)));
+        assert!(matches!(resolve_package_manager_type(&config, Some("")), Ok(PackageManagerType::Zypper)));
+        assert!(matches!(resolve_package_manager_type(&config, None), Ok(PackageManagerType::Zypper)));
+
+        config.package_manager = None;
+        assert!(matches!(resolve_package_manager_type(&config, None), Err(Error::UnsupportedPackageManager)));
+    }
+
+    #[test]
+    fn invalid_environment_variable_is_an_error_like_invalid_settings() {
+        let config = Config::default().package;
+        let settings_message = "brew".parse::<PackageManagerType>().err().unwrap();
+
+        let err = resolve_package_manager_type(&config, Some("brew")).err().unwrap();
+        assert!(matches!(&err, Error::InvalidPackageManager(PACKAGE_MANAGER_ENV_VAR, message) if *message == settings_message));
+        assert_eq!(err.to_string(), format!("PACKAGE_ASSISTANT_MANAGER: {}", settings_message));
+    }
+}
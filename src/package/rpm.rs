@@ -0,0 +1,436 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::NaiveDate;
+
+use crate::storage::PackageConfig;
+
+use super::{utilities, ChangelogEntry, ChangelogQuery, Error, PackageChangelogResult, PackageVerificationResult};
+use super::error::Result;
+
+/// Reads the name and all changelog entries of an RPM package. Packages that the rpm crate can't parse, e.g. due to
+/// newer header tags, are read with the system's `rpm` command instead.
+pub fn read_rpm_changelogs(config: &PackageConfig, path: &Path) -> Result<PackageChangelogResult> {
+    match read_rpm_changelogs_with_crate(path) {
+        Err(Error::RPMError(err)) if is_unsupported_rpm_error(&err) => read_rpm_changelogs_with_command(config, path),
+        result => result
+    }
+}
+
+/// Checks whether the rpm crate failed because the package uses something it doesn't support, rather than because
+/// the file isn't an RPM package at all
+fn is_unsupported_rpm_error(err: &::rpm::Error) -> bool {
+    matches!(err,
+        ::rpm::Error::Nom(_)
+        | ::rpm::Error::UnsupportedHeaderVersion(_)
+        | ::rpm::Error::InvalidTagDataType { .. }
+        | ::rpm::Error::UnexpectedTagDataType { .. }
+        | ::rpm::Error::InvalidTagIndex { .. }
+        | ::rpm::Error::InvalidTagValueEnumVariant { .. }
+        | ::rpm::Error::UnknownCompressorType(_))
+}
+
+fn read_rpm_changelogs_with_command(config: &PackageConfig, path: &Path) -> Result<PackageChangelogResult> {
+    let output = utilities::new_command(config, "rpm")
+        .args(["-qp", "--qf", "%{NAME}"])
+        .arg(path)
+        .output()?;
+    let name = utilities::process_cmd_output(output, Some(Error::RPMCommandError))?;
+
+    let output = utilities::new_command(config, "rpm")
+        .args(["-qp", "--changelog"])
+        .arg(path)
+        .output()?;
+    let changelog_text = utilities::process_cmd_output(output, Some(Error::RPMCommandError))?;
+
+    Ok(PackageChangelogResult { name: name.trim().to_owned(), changelogs: parse_rpm_changelog_text(&changelog_text) })
+}
+
+/// Reads the changelogs of the installed package `name` from the rpm database, leaving out those older than the
+/// query's `since`
+pub fn get_installed_rpm_changelogs(config: &PackageConfig, query: &ChangelogQuery, name: &str) -> Result<PackageChangelogResult> {
+    let output = utilities::new_command(config, "rpm")
+        .args(["-q", "--changelog", name])
+        .output()?;
+    // rpm reports packages that aren't installed on stdout
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(if output.stderr.is_empty() { &output.stdout } else { &output.stderr });
+        return Err(Error::RPMCommandError(message.trim().to_owned()))
+    }
+
+    let changelog_text = String::from_utf8(output.stdout)?;
+    let mut changelogs = parse_rpm_changelog_text(&changelog_text);
+    if let Some(since) = query.since {
+        changelogs.retain(|changelog| changelog.timestamp > since);
+    }
+
+    Ok(PackageChangelogResult { name: name.to_owned(), changelogs })
+}
+
+/// Parses the changelogs printed by `rpm -q --changelog`, where every entry starts with a `* <date> <author>` line.
+/// Since rpm only keeps the day, entries are timestamped at noon UTC, as rpmbuild does.
+pub fn parse_rpm_changelog_text(text: &str) -> Vec<ChangelogEntry> {
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        if let Some(entry) = line.strip_prefix("* ").and_then(parse_rpm_changelog_header) {
+            entries.push(entry);
+        } else if let Some(entry) = entries.last_mut() {
+            if !entry.description.is_empty() {
+                entry.description.push('\n');
+            }
+            entry.description.push_str(line);
+        }
+    }
+
+    // Entries are separated by blank lines, which aren't part of the description
+    for entry in &mut entries {
+        entry.description.truncate(entry.description.trim_end().len());
+    }
+
+    entries
+}
+
+/// Parses the date and author after the `* ` of a changelog entry, e.g. `Mon Jan 01 2024 Jane Doe <jane@example.com> - 1.0-1`
+fn parse_rpm_changelog_header(header: &str) -> Option<ChangelogEntry> {
+    let parts = header.splitn(5, ' ').collect::<Vec<&str>>();
+    let date = NaiveDate::parse_from_str(&parts.get(..4)?.join(" "), "%a %b %d %Y").ok()?;
+    let timestamp = date.and_hms_opt(12, 0, 0)?.and_utc().timestamp();
+    let author = parts.get(4).map(|author| author.trim()).filter(|author| !author.is_empty());
+
+    Some(ChangelogEntry {
+        timestamp: u64::try_from(timestamp).ok()?,
+        author: author.map(String::from),
+        description: String::new()
+    })
+}
+
+fn read_rpm_changelogs_with_crate(path: &Path) -> Result<PackageChangelogResult> {
+    let package = ::rpm::Package::open(path)?;
+    let name = package.metadata.get_name()?;
+    let changelogs = package.metadata.get_changelog_entries()?
+        .into_iter()
+        .map(|c| ChangelogEntry {
+            timestamp: c.timestamp,
+            author: Some(c.name).filter(|name| !name.is_empty()),
+            description: c.description
+        })
+        .collect::<Vec<ChangelogEntry>>();
+
+    Ok(PackageChangelogResult { name: String::from(name), changelogs })
+}
+
+/// Filters the changelogs of an RPM package to those newer than the installed version's, unless turned off in settings,
+/// and than the query's `since`
+pub fn filter_rpm_changelogs(config: &PackageConfig, query: &ChangelogQuery, mut result: PackageChangelogResult) -> Result<PackageChangelogResult> {
+    if !utilities::matches_query(&result.name, query) {
+        return Err(Error::PackageNameDoesNotMatch(result.name))
+    }
+
+    let installed_timestamp = if config.filter_changelogs_by_installed {
+        get_installed_pkg_timestamp(config, &result.name).unwrap_or(0)
+    } else {
+        0
+    };
+    let timestamp = installed_timestamp.max(query.since.unwrap_or(0));
+    result.changelogs.retain(|c| c.timestamp > timestamp);
+
+    Ok(result)
+}
+
+/// Verifies every RPM in the cached package directory with `rpm -K`
+pub fn verify_rpm_cache(config: &PackageConfig) -> Result<Vec<PackageVerificationResult>> {
+    let cache_paths = config.cached_package_path.as_ref().ok_or(Error::UnkownCachedPackagePath)?.paths();
+    let mut results = Vec::new();
+
+    for path in cache_paths.iter().map(|cache_path| utilities::find_files(cache_path, "rpm")).collect::<Result<Vec<_>>>()?.into_iter().flatten() {
+        let output = utilities::new_command(config, "rpm")
+            .arg("-K")
+            .arg(&path)
+            .output()?;
+        let message = String::from_utf8_lossy(if output.status.success() { &output.stdout } else { &output.stderr });
+
+        results.push(PackageVerificationResult {
+            path,
+            valid: output.status.success(),
+            message: message.trim().to_owned()
+        });
+    }
+
+    Ok(results)
+}
+
+/// Lists the names of all installed packages with a single `rpm -qa` call
+pub fn get_installed_rpm_names(config: &PackageConfig) -> Result<HashSet<String>> {
+    let output = utilities::new_command(config, "rpm")
+        .args(["-qa", "--qf", "%{NAME}\\n"])
+        .output()?;
+    let stdout = utilities::process_cmd_output(output, Some(Error::RPMCommandError))?;
+
+    Ok(utilities::parse_lines(&stdout).into_iter().collect())
+}
+
+pub fn get_installed_pkg_timestamp(config: &PackageConfig, name: &str) -> Result<u64> {
+    let output = utilities::new_command(config, "rpm")
+        .args(["-q", name, "--qf", "%{CHANGELOGTIME}"])
+        .output()?;
+
+    let stdout = utilities::process_cmd_output(output, Some(Error::RPMCommandError))?;
+    if let Some(first_line) = stdout.lines().next() {
+        Ok(first_line.parse::<u64>()?)
+    } else {
+        Err(Error::InvalidRPMResponse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    use crate::storage::{Config, PackageConfig};
+
+    use super::*;
+
+    /// A shell script standing in for the rpm binary, which is removed when dropped
+    struct FakeRpm(PathBuf);
+
+    impl FakeRpm {
+        fn new(name: &str, script: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("package-assistant-rpm-test-{}-{}", std::process::id(), name));
+            fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            Self(path)
+        }
+
+        fn config(&self) -> PackageConfig {
+            let mut config = Config::default().package;
+            config.rpm_path = Some(self.0.clone());
+            config
+        }
+    }
+
+    impl Drop for FakeRpm {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn installed_names_filter_out_packages_that_arent_installed() {
+        let rpm = FakeRpm::new("installed-names", "printf 'vim\\nbash\\n\\nglibc\\n'");
+        let installed = get_installed_rpm_names(&rpm.config()).unwrap();
+        assert_eq!(installed, HashSet::from([String::from("vim"), String::from("bash"), String::from("glibc")]));
+
+        let query = ChangelogQuery { installed: Some(installed), ..Default::default() };
+        let matching = ["vim", "emacs", "glibc", "nano"].into_iter()
+            .filter(|name| utilities::matches_query(name, &query))
+            .collect::<Vec<&str>>();
+        assert_eq!(matching, ["vim", "glibc"]);
+    }
+
+    #[test]
+    fn installed_changelogs_leave_out_entries_before_last_update() {
+        let rpm = FakeRpm::new("since", "printf '%s\\n' \\
+            '* Wed Jan 10 2024 Jane Doe <jane@example.com> - 9.1-2' '- Fix a crash' '' \\
+            '* Mon Jan 01 2024 Jane Doe <jane@example.com> - 9.1-1' '- Update to 9.1' '' \\
+            '* Fri Dec 01 2023 John Doe <john@example.com> - 9.0-1' '- Update to 9.0'");
+        // Jan 05 2024, between the first two entries
+        let query = ChangelogQuery { since: Some(1704456000), ..Default::default() };
+
+        let result = get_installed_rpm_changelogs(&rpm.config(), &query, "vim").unwrap();
+        let descriptions = result.changelogs.iter().map(|changelog| changelog.description.as_str()).collect::<Vec<&str>>();
+        assert_eq!(descriptions, ["- Fix a crash"]);
+    }
+
+    #[test]
+    fn changelogs_are_filtered_by_installed_version_unless_turned_off() {
+        // The installed version's newest changelog entry is from Jan 01 2024
+        let rpm = FakeRpm::new("installed-timestamp", "echo 1704110400");
+        let result = || PackageChangelogResult {
+            name: String::from("vim"),
+            changelogs: [1704888000, 1704110400, 1701432000].into_iter()
+                .map(|timestamp| ChangelogEntry { timestamp, author: None, description: String::new() })
+                .collect()
+        };
+        let timestamps = |result: PackageChangelogResult| result.changelogs.iter()
+            .map(|changelog| changelog.timestamp)
+            .collect::<Vec<u64>>();
+
+        let mut config = rpm.config();
+        let filtered = filter_rpm_changelogs(&config, &ChangelogQuery::default(), result()).unwrap();
+        assert_eq!(timestamps(filtered), [1704888000]);
+
+        config.filter_changelogs_by_installed = false;
+        let unfiltered = filter_rpm_changelogs(&config, &ChangelogQuery::default(), result()).unwrap();
+        assert_eq!(timestamps(unfiltered), [1704888000, 1704110400, 1701432000]);
+
+        let query = ChangelogQuery { name: Some(String::from("emacs")), ..Default::default() };
+        assert!(matches!(filter_rpm_changelogs(&config, &query, result()), Err(Error::PackageNameDoesNotMatch(name)) if name == "vim"));
+    }
+
+    #[test]
+    fn packages_are_read_with_the_rpm_crate() {
+        // The rpm command isn't needed for packages that the crate can parse
+        let rpm = FakeRpm::new("crate", "exit 1");
+        let path = std::env::temp_dir().join(format!("package-assistant-rpm-test-{}-crate.rpm", std::process::id()));
+        ::rpm::PackageBuilder::new("vim", "9.1", "Vim", "x86_64", "Vi IMproved")
+            .add_changelog_entry("Jane Doe <jane@example.com> - 9.1-2", "- Fix a crash", 1704888000)
+            .add_changelog_entry("", "- Update to 9.1", 1704110400)
+            .build()
+            .unwrap()
+            .write_file(&path)
+            .unwrap();
+
+        let result = read_rpm_changelogs(&rpm.config(), &path);
+        let _ = fs::remove_file(&path);
+
+        let result = result.unwrap();
+        assert_eq!(result.name, "vim");
+        let changelogs = result.changelogs.iter()
+            .map(|changelog| (changelog.timestamp, changelog.author.as_deref(), changelog.description.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(changelogs, [
+            (1704888000, Some("Jane Doe <jane@example.com> - 9.1-2"), "- Fix a crash"),
+            (1704110400, None, "- Update to 9.1")
+        ]);
+    }
+
+    #[test]
+    fn files_that_arent_packages_arent_read_with_rpm_command() {
+        let rpm = FakeRpm::new("not-a-package", "printf vim");
+        let path = std::env::temp_dir().join(format!("package-assistant-rpm-test-{}-not-a-package.rpm", std::process::id()));
+        fs::write(&path, "not a package").unwrap();
+
+        let result = read_rpm_changelogs(&rpm.config(), &path);
+        let _ = fs::remove_file(&path);
+
+        assert!(matches!(result, Err(Error::RPMError(_))));
+    }
+
+    #[test]
+    fn cached_packages_are_verified_with_rpm_command() {
+        let rpm = FakeRpm::new("verify", r#"[ "$1" = -K ] || exit 2
+case "$2" in
+    *broken*) echo "$2: DIGESTS SIGNATURES NOT OK" >&2; exit 1 ;;
+    *) echo "$2: digests signatures OK" ;;
+esac"#);
+        let dir = std::env::temp_dir().join(format!("package-assistant-rpm-test-{}-verify-cache", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file_name in ["vim.rpm", "broken.rpm", "notes.txt"] {
+            fs::write(dir.join(file_name), "").unwrap();
+        }
+        let mut config = rpm.config();
+        config.cached_package_path = Some(crate::storage::PathList::Single(dir.clone()));
+
+        let results = verify_rpm_cache(&config);
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut results = results.unwrap().into_iter()
+            .map(|result| (result.path.file_name().unwrap().to_string_lossy().into_owned(), result.valid, result.message))
+            .collect::<Vec<_>>();
+        results.sort();
+        assert_eq!(results, [
+            (String::from("broken.rpm"), false, format!("{}: DIGESTS SIGNATURES NOT OK", dir.join("broken.rpm").display())),
+            (String::from("vim.rpm"), true, format!("{}: digests signatures OK", dir.join("vim.rpm").display()))
+        ]);
+    }
+
+    #[test]
+    fn packages_the_crate_cant_parse_are_read_with_rpm_command() {
+        let rpm = FakeRpm::new("fallback", "case \"$2\" in
+            --qf) printf vim ;;
+            --changelog) printf '%s\\n' '* Wed Jan 10 2024 Jane Doe <jane@example.com> - 9.1-2' '- Fix a crash' ;;
+            *) exit 1 ;;
+        esac");
+        // A lead followed by a header with a version that the rpm crate doesn't support
+        let path = std::env::temp_dir().join(format!("package-assistant-rpm-test-{}-unsupported.rpm", std::process::id()));
+        let mut bytes = vec![0xed, 0xab, 0xee, 0xdb, 3, 0];
+        bytes.resize(96, 0);
+        bytes.extend([0x8e, 0xad, 0xe8, 0x02]);
+        bytes.resize(4096, 0);
+        fs::write(&path, bytes).unwrap();
+
+        let result = read_rpm_changelogs(&rpm.config(), &path);
+        let _ = fs::remove_file(&path);
+
+        let result = result.unwrap();
+        assert_eq!(result.name, "vim");
+        assert_eq!(result.changelogs.len(), 1);
+        assert_eq!(result.changelogs[0].description, "- Fix a crash");
+    }
+
+    #[test]
+    fn rpm_changelog_text_is_split_into_entries() {
+        let text = "\
+* Wed Jan 10 2024 Jane Doe <jane@example.com> - 9.1-2
+- Fix a crash
+  when opening large files
+
+* Mon Jan 01 2024 Jane Doe <jane@example.com> - 9.1-1
+- Update to 9.1
+
+* Fri Dec 01 2023
+- Update to 9.0
+";
+        let entries = parse_rpm_changelog_text(text);
+        let summary = entries.iter()
+            .map(|entry| (entry.timestamp, entry.author.as_deref(), entry.description.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            (1704888000, Some("Jane Doe <jane@example.com> - 9.1-2"), "- Fix a crash\n  when opening large files"),
+            (1704110400, Some("Jane Doe <jane@example.com> - 9.1-1"), "- Update to 9.1"),
+            (1701432000, None, "- Update to 9.0")
+        ]);
+    }
+
+    #[test]
+    fn installed_changelogs_are_read_from_rpm_query() {
+        let rpm = FakeRpm::new("installed-changelog", r#"[ "$3" = vim ] || { echo "package $3 is not installed"; exit 1; }
+cat <<'EOF'
+* Wed Jan 10 2024 Jane Doe <jane@example.com>
+- Update to version 9.1.0016:
+  * patch 9.1.0016: default diff highlighting is too noisy
+  * patch 9.1.0015: i_CTRL-R- no longer works
+
+* Mon Jan 01 2024 John Doe <john@example.com>
+- Add vim-9.1-fix-build.patch (boo#1218500)
+EOF"#);
+
+        let result = get_installed_rpm_changelogs(&rpm.config(), &ChangelogQuery::default(), "vim").unwrap();
+        assert_eq!(result.name, "vim");
+        assert_eq!(result.changelogs.len(), 2);
+        assert_eq!(result.changelogs[0].author.as_deref(), Some("Jane Doe <jane@example.com>"));
+        assert_eq!(result.changelogs[0].description, "- Update to version 9.1.0016:\n  \
+            * patch 9.1.0016: default diff highlighting is too noisy\n  \
+            * patch 9.1.0015: i_CTRL-R- no longer works");
+        assert_eq!(result.changelogs[1].timestamp, 1704110400);
+
+        let err = get_installed_rpm_changelogs(&rpm.config(), &ChangelogQuery::default(), "emacs").err().unwrap();
+        assert!(matches!(err, Error::RPMCommandError(message) if message == "package emacs is not installed"));
+    }
+
+    #[test]
+    fn summary_counts_entries_newer_than_installed() {
+        // vim's installed version is from Jan 01 2024, and git isn't installed
+        let rpm = FakeRpm::new("summary", r#"[ "$2" = vim ] && echo 1704110400 || { echo "package $2 is not installed"; exit 1; }"#);
+        let result = |name: &str, timestamps: &[u64]| PackageChangelogResult {
+            name: String::from(name),
+            changelogs: timestamps.iter()
+                .map(|timestamp| ChangelogEntry { timestamp: *timestamp, author: None, description: String::new() })
+                .collect()
+        };
+        let config = rpm.config();
+        let results = [
+            result("vim", &[1704974400, 1704888000, 1704110400, 1701432000]),
+            result("git", &[1704888000, 1701432000])
+        ].into_iter()
+            .map(|result| filter_rpm_changelogs(&config, &ChangelogQuery::default(), result).unwrap())
+            .collect::<Vec<PackageChangelogResult>>();
+
+        let summary = crate::package::summarize_changelog_results(&results).into_iter()
+            .map(|item| (item.name, item.count, item.newest_timestamp))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [(String::from("vim"), 2, 1704974400), (String::from("git"), 2, 1704888000)]);
+    }
+}
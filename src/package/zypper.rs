@@ -1,58 +1,140 @@
+use std::collections::HashSet;
 use std::path::Path;
-use std::process::Command;
 
+use nix::unistd::{getgid, getuid, Gid, Uid};
 use quick_xml::events::attributes::Attribute;
 use quick_xml::events::Event;
 use quick_xml::Reader;
 
 use crate::storage::PackageConfig;
 
-use super::{utilities, ChangelogQuery, Error, PackageChangelogResult, PackageManager, PackageUpdateItem};
+use super::{rpm, utilities, ChangelogQuery, Error, PackageChangelogResult, PackageManager, PackageUpdateItem, PackageVerificationResult, PatchItem, RepoInfo, UpdateSummary};
 use super::error::Result;
 
 pub struct ZypperManager<'a> {
     pub config: &'a PackageConfig
 }
 
+pub fn create_manager(config: &PackageConfig) -> Box<dyn PackageManager + '_> {
+    Box::new(ZypperManager { config })
+}
+
+impl ZypperManager<'_> {
+    /// zypper as it's written in the commands that are run in a shell
+    fn zypper(&self) -> String {
+        utilities::get_shell_program(self.config, "zypper")
+    }
+}
+
 impl<'a> PackageManager for ZypperManager<'a> {
 
     fn get_config(&self) -> &PackageConfig {
         self.config
     }
 
-    fn get_package_changelogs_result(&self, query: &ChangelogQuery, path: &Path) -> Result<PackageChangelogResult> {
-        utilities::get_rpm_changelogs_result(query, path)
+    fn package_extensions(&self) -> &[&str] {
+        &["rpm"]
+    }
+
+    fn read_package_changelogs(&self, path: &Path) -> Result<PackageChangelogResult> {
+        rpm::read_rpm_changelogs(self.config, path)
+    }
+
+    fn filter_package_changelogs(&self, query: &ChangelogQuery, result: PackageChangelogResult) -> Result<PackageChangelogResult> {
+        rpm::filter_rpm_changelogs(self.config, query, result)
+    }
+
+    fn verify_cache(&self) -> Result<Vec<PackageVerificationResult>> {
+        rpm::verify_rpm_cache(self.config)
+    }
+
+    fn installed_packages(&self) -> Result<HashSet<String>> {
+        rpm::get_installed_rpm_names(self.config)
+    }
+
+    fn get_installed_changelog_results(&self, query: &ChangelogQuery, name: &str) -> Result<Vec<PackageChangelogResult>> {
+        Ok(vec![rpm::get_installed_rpm_changelogs(self.config, query, name)?])
+    }
+
+    fn check_update(&self, refresh: bool) -> Result<UpdateSummary> {
+        let mut command = utilities::new_check_update_override(self.config).unwrap_or_else(|| {
+            let mut command = utilities::new_command(self.config, "zypper");
+            if !refresh {
+                command.arg("--no-refresh");
+            }
+            command.args(["--xmlout", "lu"]);
+            command
+        });
+        let output = command.output()?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::ZypperError))?;
+
+        Ok(UpdateSummary::new(parse_update_list(&stdout)?))
+    }
+
+    fn list_patches(&self) -> Result<Vec<PatchItem>> {
+        let output = utilities::new_command(self.config, "zypper")
+            .args(["--xmlout", "lp"])
+            .output()?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::ZypperError))?;
+        let mut reader = Reader::from_str(stdout.as_str());
+        let mut patches = Vec::new();
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"update" => {
+                    let mut patch = PatchItem { name: String::new(), category: None, severity: None, needs_reboot: false };
+
+                    for attr_result in e.attributes() {
+                        let attr = attr_result?;
+
+                        match attr.key.as_ref() {
+                            b"name" => patch.name = attr_to_string(attr),
+                            b"category" => patch.category = Some(attr_to_string(attr)),
+                            b"severity" => patch.severity = Some(attr_to_string(attr)),
+                            b"restart" => patch.needs_reboot = attr.value.as_ref() == b"true",
+                            _ => ()
+                        }
+                    }
+
+                    if !patch.name.is_empty() {
+                        patches.push(patch);
+                    }
+                },
+                Event::Eof => break,
+                _ => ()
+            }
+        }
+
+        Ok(patches)
     }
 
-    fn check_update(&self) -> Result<Vec<PackageUpdateItem>> {
-        let output = Command::new("zypper")
-            .args(["--xmlout", "lu"])
+    fn list_repositories(&self) -> Result<Vec<RepoInfo>> {
+        let output = utilities::new_command(self.config, "zypper")
+            .args(["--xmlout", "lr"])
             .output()?;
-        let stdout = utilities::process_cmd_output(output, Some(|err| Error::ZypperError(err)))?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::ZypperError))?;
         let mut reader = Reader::from_str(stdout.as_str());
-        let mut items = Vec::new();
+        let mut repos = Vec::new();
 
         loop {
             match reader.read_event()? {
-                Event::Start(e) if e.name().as_ref() == b"update" =>{
-                    let mut name: String = String::new();
-                    let mut edition: Option<String> = None;
-                    let mut edition_old: Option<String> = None;
-                    let attributes = e.attributes();
+                Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"repo" => {
+                    let mut repo = RepoInfo { id: String::new(), name: String::new(), enabled: false, priority: None };
 
-                    for attr_result in attributes {
+                    for attr_result in e.attributes() {
                         let attr = attr_result?;
 
                         match attr.key.as_ref() {
-                            b"name" => name = attr_to_string(attr),
-                            b"edition" => edition = Some(attr_to_string(attr)),
-                            b"edition-old" => edition_old = Some(attr_to_string(attr)),
+                            b"alias" => repo.id = attr_to_string(attr),
+                            b"name" => repo.name = attr_to_string(attr),
+                            b"enabled" => repo.enabled = attr.value.as_ref() == b"1",
+                            b"priority" => repo.priority = attr_to_string(attr).parse().ok(),
                             _ => ()
                         }
                     }
 
-                    if !name.is_empty() {
-                        items.push(PackageUpdateItem { name, new_version: edition, old_version: edition_old });
+                    if !repo.id.is_empty() {
+                        repos.push(repo);
                     }
                 },
                 Event::Eof => break,
@@ -60,10 +142,380 @@ impl<'a> PackageManager for ZypperManager<'a> {
             }
         }
 
-        Ok(items)
+        Ok(repos)
+    }
+
+    fn get_repository_enabled_command(&self, id: &str, enabled: bool) -> Option<String> {
+        let flag = if enabled { "--enable" } else { "--disable" };
+        Some(format!("{} modifyrepo {} {}", self.zypper(), flag, utilities::quote_shell_arg(id)))
+    }
+
+    fn apply_patch(&self, name: &str, interactive: bool, elevate_privileges: bool) -> Result<()> {
+        let command = if interactive {
+            format!("{} install -t patch {}", self.zypper(), utilities::quote_shell_arg(name))
+        } else {
+            format!("{} --non-interactive install -t patch {}", self.zypper(), utilities::quote_shell_arg(name))
+        };
+
+        let command = match self.get_reboot_patches_command(&command) {
+            Some(reboot_patches_command) if !interactive && self.config.include_reboot_patches => reboot_patches_command,
+            _ => command
+        };
+
+        if interactive {
+            utilities::run_interactive_shell_command(self.config, &command, elevate_privileges, Some(Error::update_error))
+        } else {
+            utilities::run_shell_command(self.config, &command, elevate_privileges, Some(Error::update_error))
+        }
+    }
+
+    fn download_package(&self, item: &PackageUpdateItem, path: &Path) -> Result<()> {
+        let command = get_download_package_command(&self.zypper(), &item.name, path, getuid(), getgid());
+        utilities::run_shell_command(self.config, &command, true, Some(Error::download_error))
+    }
+
+    fn get_download_dir_command(&self, command: &str, download_dir: &Path) -> Option<String> {
+        // --pkg-cache-dir is a global option, so it has to come before the subcommand
+        let args = utilities::strip_shell_program(self.config, command, "zypper")?;
+        Some(format!("{} --pkg-cache-dir {} {}", self.zypper(), utilities::quote_shell_arg(&download_dir.to_string_lossy()), args))
+    }
+
+    fn get_default_dist_upgrade_command(&self, interactive: bool) -> Option<String> {
+        if interactive {
+            Some(format!("{} dist-upgrade", self.zypper()))
+        } else {
+            Some(format!("{} --non-interactive dist-upgrade", self.zypper()))
+        }
+    }
+
+    fn get_security_update_command(&self, interactive: bool) -> Option<String> {
+        if interactive {
+            Some(format!("{} patch --category security", self.zypper()))
+        } else {
+            Some(format!("{} --non-interactive patch --category security", self.zypper()))
+        }
+    }
+
+    fn get_package_update_command(&self, interactive: bool) -> Option<String> {
+        // The configured command may be a distribution upgrade, which can't be restricted to some packages
+        if interactive {
+            Some(format!("{} update", self.zypper()))
+        } else {
+            Some(format!("{} --non-interactive update", self.zypper()))
+        }
+    }
+
+    fn get_reboot_patches_command(&self, command: &str) -> Option<String> {
+        // Like --pkg-cache-dir, this is a global option that has to come before the subcommand
+        let args = utilities::strip_shell_program(self.config, command, "zypper")?;
+        Some(format!("{} --non-interactive-include-reboot-patches {}", self.zypper(), args))
+    }
+
+    fn get_fail_on_conflict_command(&self, command: &str) -> Option<String> {
+        let args = utilities::strip_shell_program(self.config, command, "zypper")?;
+        let subcommand = args.split_whitespace().find(|arg| !arg.starts_with('-'))?;
+
+        // zypper resolves problems aggressively in non-interactive mode unless told not to, and only a distribution
+        // upgrade may change vendors or downgrade
+        if matches!(subcommand, "dup" | "dist-upgrade") {
+            Some(format!("{} --no-force-resolution --no-allow-vendor-change --no-allow-downgrade", command))
+        } else {
+            Some(format!("{} --no-force-resolution", command))
+        }
+    }
+
+    fn get_include_optional_args(&self) -> Option<String> {
+        // Installs packages that became recommended by the updated ones
+        Some(String::from("--recommends"))
+    }
+
+    fn get_preview_command(&self, _interactive_command: &str, noconfirm_command: &str) -> Option<String> {
+        Some(format!("{} --dry-run", noconfirm_command))
+    }
+
+    fn get_default_offline_update_command(&self) -> Option<String> {
+        Some(String::from(utilities::PACKAGEKIT_OFFLINE_UPDATE_COMMAND))
+    }
+
+    fn services_needing_restart(&self) -> Result<Vec<String>> {
+        let output = utilities::new_command(self.config, "zypper")
+            .args(["ps", "-sss"])
+            .output()?;
+        let stdout = utilities::process_cmd_output(output, Some(Error::ZypperError))?;
+
+        Ok(utilities::parse_lines(&stdout))
+    }
+
+    fn reboot_required(&self) -> Option<bool> {
+        let output = utilities::new_command(self.config, "zypper")
+            .arg("needs-rebooting")
+            .output()
+            .ok()?;
+
+        // zypper exits with ZYPPER_EXIT_INF_REBOOT_NEEDED if a reboot is needed
+        match output.status.code() {
+            Some(0) => Some(false),
+            Some(102) => Some(true),
+            _ => None
+        }
+    }
+}
+
+/// Parses the updates listed in the XML output of `zypper --xmlout list-updates`
+fn parse_update_list(output: &str) -> Result<Vec<PackageUpdateItem>> {
+    let mut reader = Reader::from_str(output);
+    let mut items = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) if e.name().as_ref() == b"update" =>{
+                let mut name: String = String::new();
+                let mut edition: Option<String> = None;
+                let mut edition_old: Option<String> = None;
+                let mut arch: Option<String> = None;
+                let attributes = e.attributes();
+
+                for attr_result in attributes {
+                    let attr = attr_result?;
+
+                    match attr.key.as_ref() {
+                        b"name" => name = attr_to_string(attr),
+                        b"edition" => edition = Some(attr_to_string(attr)),
+                        b"edition-old" => edition_old = Some(attr_to_string(attr)),
+                        b"arch" => arch = Some(attr_to_string(attr)),
+                        _ => ()
+                    }
+                }
+
+                if !name.is_empty() {
+                    items.push(PackageUpdateItem { name, new_version: edition, old_version: edition_old, category: None, arch });
+                }
+            },
+            Event::Eof => break,
+            _ => ()
+        }
     }
+
+    Ok(items)
+}
+
+/// Creates the command that downloads package `name` into `path`. zypper has to run as root to download, so the
+/// downloaded files are handed over to the user with `uid` and `gid` afterwards, even if the download failed.
+fn get_download_package_command(zypper: &str, name: &str, path: &Path, uid: Uid, gid: Gid) -> String {
+    let path = utilities::quote_shell_arg(&path.to_string_lossy());
+    format!("{} --non-interactive --pkg-cache-dir {} download {}; status=$?; chown -R {}:{} {}; exit $status",
+        zypper, path, utilities::quote_shell_arg(name), uid, gid, path)
 }
 
 fn attr_to_string(attr: Attribute) -> String {
     String::from_utf8_lossy(attr.value.as_ref()).to_string()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use nix::unistd::{Gid, Uid};
+
+    use crate::storage::{Config, PackageConfig};
+
+    use super::super::{ChangelogQuery, DirScan, Error, PackageManager, PackageUpdateItem, UpdateOptions};
+    use super::{get_download_package_command, parse_update_list, ZypperManager};
+
+    fn test_config() -> PackageConfig {
+        Config::default().package
+    }
+
+    #[test]
+    fn fail_on_conflict_locks_vendor_and_downgrades_for_dist_upgrade() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let command = manager.build_update_command("zypper dup -y", &options, false).unwrap();
+        assert_eq!(command, "zypper dup -y --no-force-resolution --no-allow-vendor-change --no-allow-downgrade");
+    }
+
+    #[test]
+    fn fail_on_conflict_only_disables_force_resolution_for_other_subcommands() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let command = manager.build_update_command("zypper --non-interactive patch --category security", &options, false).unwrap();
+        assert_eq!(command, "zypper --non-interactive patch --category security --no-force-resolution");
+    }
+
+    #[test]
+    fn fail_on_conflict_is_unsupported_for_wrapped_commands() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let result = manager.build_update_command("sudo /usr/local/bin/update.sh", &options, false);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn include_optional_adds_recommends() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        let options = UpdateOptions { include_optional: true, ..Default::default() };
+        let command = manager.build_update_command("zypper dup", &options, true).unwrap();
+        assert_eq!(command, "zypper dup --recommends");
+    }
+
+    #[test]
+    fn reboot_patches_flag_is_added_to_noconfirm_updates_when_enabled() {
+        let mut config = test_config();
+        config.include_reboot_patches = true;
+        let manager = ZypperManager { config: &config };
+        let command = manager.build_update_command("zypper dup -y", &UpdateOptions::default(), false).unwrap();
+        assert_eq!(command, "zypper --non-interactive-include-reboot-patches dup -y");
+    }
+
+    #[test]
+    fn reboot_patches_flag_is_left_out_by_default_and_for_interactive_updates() {
+        let mut config = test_config();
+        let command = ZypperManager { config: &config }.build_update_command("zypper dup -y", &UpdateOptions::default(), false).unwrap();
+        assert_eq!(command, "zypper dup -y");
+
+        config.include_reboot_patches = true;
+        let command = ZypperManager { config: &config }.build_update_command("zypper dup", &UpdateOptions::default(), true).unwrap();
+        assert_eq!(command, "zypper dup");
+    }
+
+    #[test]
+    fn repository_enabled_command_uses_modifyrepo() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        assert_eq!(manager.get_repository_enabled_command("repo-oss", true).as_deref(), Some("zypper modifyrepo --enable 'repo-oss'"));
+        assert_eq!(manager.get_repository_enabled_command("repo-oss", false).as_deref(), Some("zypper modifyrepo --disable 'repo-oss'"));
+    }
+
+    #[test]
+    fn built_in_commands_use_configured_zypper_path() {
+        let mut config = test_config();
+        config.package_manager_path = Some(PathBuf::from("/opt/zypper/bin/zypper"));
+        config.include_reboot_patches = true;
+        let manager = ZypperManager { config: &config };
+
+        assert_eq!(manager.get_security_update_command(false).as_deref(),
+            Some("'/opt/zypper/bin/zypper' --non-interactive patch --category security"));
+        assert_eq!(manager.get_default_dist_upgrade_command(true).as_deref(), Some("'/opt/zypper/bin/zypper' dist-upgrade"));
+        assert_eq!(manager.get_repository_enabled_command("oss", false).as_deref(),
+            Some("'/opt/zypper/bin/zypper' modifyrepo --disable 'oss'"));
+        assert!(get_download_package_command(&manager.zypper(), "vim", Path::new("/tmp/vim"), Uid::from_raw(1000), Gid::from_raw(100))
+            .starts_with("'/opt/zypper/bin/zypper' --non-interactive --pkg-cache-dir '/tmp/vim' download 'vim';"));
+
+        // Configured commands are rewritten whether they run zypper by name or by its path
+        let command = manager.build_update_command("zypper --non-interactive update", &UpdateOptions::default(), false).unwrap();
+        assert_eq!(command, "'/opt/zypper/bin/zypper' --non-interactive-include-reboot-patches --non-interactive update");
+        let options = UpdateOptions { fail_on_conflict: true, ..Default::default() };
+        let command = manager.build_update_command("'/opt/zypper/bin/zypper' dup -y", &options, false).unwrap();
+        assert_eq!(command, "'/opt/zypper/bin/zypper' --non-interactive-include-reboot-patches dup -y \
+            --no-force-resolution --no-allow-vendor-change --no-allow-downgrade");
+    }
+
+    #[test]
+    fn chosen_packages_are_updated_with_zypper_update() {
+        let mut config = test_config();
+        config.noconfirm_update_command = String::from("zypper --non-interactive dup");
+        config.ignored_packages = vec![String::from("kernel*")];
+        let manager = ZypperManager { config: &config };
+        let options = UpdateOptions {
+            packages: vec![String::from("vim"), String::from("kernel-default"), String::from("git")],
+            ..Default::default()
+        };
+
+        let command = manager.get_selected_update_command(&options, false);
+        assert_eq!(manager.build_update_command(&command, &options, false).unwrap(), "zypper --non-interactive update 'vim' 'git'");
+        let command = manager.get_selected_update_command(&options, true);
+        assert_eq!(manager.build_update_command(&command, &options, true).unwrap(), "zypper update 'vim' 'git'");
+
+        // Without chosen packages, the configured command is used
+        assert_eq!(manager.get_selected_update_command(&UpdateOptions::default(), false), "zypper --non-interactive dup");
+    }
+
+    #[test]
+    fn security_update_command_uses_security_patches() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        assert_eq!(manager.get_security_update_command(true).as_deref(), Some("zypper patch --category security"));
+        assert_eq!(manager.get_security_update_command(false).as_deref(), Some("zypper --non-interactive patch --category security"));
+    }
+
+    #[test]
+    fn download_dir_command_puts_pkg_cache_dir_before_subcommand() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        let command = manager.get_download_dir_command("zypper dup -dy", Path::new("/tmp/updates"));
+        assert_eq!(command.as_deref(), Some("zypper --pkg-cache-dir '/tmp/updates' dup -dy"));
+        assert_eq!(manager.get_download_dir_command("./download.sh", Path::new("/tmp/updates")), None);
+    }
+
+    fn update_item(name: &str) -> PackageUpdateItem {
+        PackageUpdateItem { name: String::from(name), old_version: None, new_version: None, category: None, arch: Some(String::from("x86_64")) }
+    }
+
+    #[test]
+    fn reboot_triggers_match_kernel_but_not_vim() {
+        let config = test_config();
+        let manager = ZypperManager { config: &config };
+        assert!(manager.reboot_triggered_by(&[update_item("kernel-default")]));
+        assert!(!manager.reboot_triggered_by(&[update_item("vim")]));
+    }
+
+    #[test]
+    fn downloaded_packages_are_handed_to_the_user() {
+        let command = get_download_package_command("zypper", "vim", Path::new("/home/user/.cache/vim"), Uid::from_raw(1000), Gid::from_raw(100));
+        assert_eq!(command, "zypper --non-interactive --pkg-cache-dir '/home/user/.cache/vim' download 'vim'; status=$?; \
+            chown -R 1000:100 '/home/user/.cache/vim'; exit $status");
+    }
+
+    #[test]
+    fn update_list_keeps_arch_of_each_update() {
+        let output = r#"<?xml version='1.0'?>
+<stream>
+<update-status version="0.6">
+<update-list>
+<update kind="package" name="glibc" edition="2.40-2.1" arch="x86_64" edition-old="2.40-1.1">
+<summary>Standard Shared Libraries</summary>
+</update>
+<update kind="package" name="glibc-32bit" edition="2.40-2.1" arch="i586" edition-old="2.40-1.1">
+<summary>Standard Shared Libraries</summary>
+</update>
+<update kind="package" name="timezone" edition="2024b-1.1" arch="noarch" edition-old="2024a-1.1">
+<summary>Timezone Descriptions</summary>
+</update>
+</update-list>
+</update-status>
+</stream>"#;
+        let items = parse_update_list(output).unwrap();
+        let arches = items.iter().map(|item| (item.name.as_str(), item.arch.as_deref())).collect::<Vec<_>>();
+        assert_eq!(arches, [("glibc", Some("x86_64")), ("glibc-32bit", Some("i586")), ("timezone", Some("noarch"))]);
+        assert_eq!(items[0].old_version.as_deref(), Some("2.40-1.1"));
+    }
+
+    #[test]
+    fn scan_ignores_other_files_and_reports_broken_packages() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-zypper-scan-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("README.txt"), "Not a package").unwrap();
+        fs::write(dir.join("vim-9.1-1.1.x86_64.rpm.asc"), "Not a package either").unwrap();
+        // The lead of an RPM package that ends right after its magic number
+        fs::write(dir.join("vim-9.1-1.1.x86_64.rpm"), b"\xed\xab\xee\xdb\x03\x00").unwrap();
+
+        let config = test_config();
+        let query = ChangelogQuery { skip_index: true, ..Default::default() };
+        let mut scan = DirScan::default();
+        let results = ZypperManager { config: &config }.scan_dir_changelog_results(&query, std::slice::from_ref(&dir), &mut scan);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(results.unwrap().is_empty());
+        assert_eq!(scan.package_count, 1);
+        assert!(scan.skipped_paths.is_empty());
+        let failed_names = scan.failed_packages.iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(failed_names, ["vim-9.1-1.1.x86_64.rpm"]);
+    }
+}
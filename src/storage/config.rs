@@ -1,19 +1,236 @@
+use nix::unistd::{access, AccessFlags};
 use serde::{de::Error, Deserialize, Serialize};
-use std::path::PathBuf;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use super::toml::TomlStorage;
+use super::error::Error as StorageError;
+use super::toml::{get_xdg_dir_path, TomlStorage};
 
 const CONFIG_HOME: &str = "XDG_CONFIG_HOME";
 const DEFAULT_CONFIG_PATH: &str = ".config";
 const CONFIG_FILE_NAME: &str = "settings.toml";
+/// Relative to the root directory, so that tests can use another one
+const SYSTEM_CONFIG_PATH: &str = "etc/package-assistant";
+const SYSTEM_CONFIG_PARENT_PATH: &str = "etc";
+/// Directory of the defaults that distributions can ship, which the settings file is layered over
+const VENDOR_CONFIG_PATH: &str = "/usr/lib/package-assistant";
+
+pub const DEFAULT_CHANGELOG_DATE_FORMAT: &str = "%a %b %d %Y";
+/// Version of the settings format written by this version of package-assistant
+pub const CURRENT_CONFIG_VERSION: u32 = 1;
 
 #[derive(Deserialize, Serialize)]
 pub struct Config {
+    /// Version of the settings format, which is 0 for settings written before it was versioned. Older versions are
+    /// migrated to the current one when the settings are read.
+    #[serde(default)]
+    pub config_version: u32,
     pub service: ServiceConfig,
     pub package: PackageConfig
 }
 
+impl Config {
+    /// Checks the settings for problems that parsing doesn't catch, such as missing commands. Returns
+    /// `Error::InvalidConfig` with every problem that was found.
+    pub fn validate(&self) -> Result<(), StorageError> {
+        let mut problems = Vec::new();
+        if self.package.package_manager.is_none() {
+            problems.push(String::from("'package_manager' must be set"));
+        }
+
+        for (name, command) in [
+            ("download_command", &self.package.download_command),
+            ("update_command", &self.package.update_command),
+            ("noconfirm_update_command", &self.package.noconfirm_update_command)
+        ] {
+            if command.trim().is_empty() {
+                problems.push(format!("'{}' must not be empty", name));
+            }
+        }
+
+        if self.service.update_check_frequency == 0 {
+            problems.push(String::from("'update_check_frequency' must be at least 1 minute"));
+        }
+
+        if self.config_version > CURRENT_CONFIG_VERSION {
+            problems.push(format!("'config_version' {} is newer than this version of package-assistant supports", self.config_version));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(StorageError::InvalidConfig(problems))
+        }
+    }
+
+    /// Since settings before version 1 had to spell out the commands, fills the ones that are left empty
+    fn migrate_v0(&mut self) {
+        self.fill_default_commands();
+    }
+
+    /// Fills the update and download commands that are left empty with the defaults of the chosen package manager
+    fn fill_default_commands(&mut self) {
+        let Some(package_manager) = self.package.package_manager else { return };
+        let (download_command, update_command, noconfirm_update_command) = package_manager.default_commands();
+
+        for (command, default_command) in [
+            (&mut self.package.download_command, download_command),
+            (&mut self.package.update_command, update_command),
+            (&mut self.package.noconfirm_update_command, noconfirm_update_command)
+        ] {
+            if command.trim().is_empty() {
+                *command = String::from(default_command);
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings file at `path` layered over the vendor defaults at `vendor_path`, if they exist
+    fn fetch_layered(vendor_path: &Path, path: &Path) -> Result<Self, StorageError> {
+        let Some(vendor_table) = read_toml_table(vendor_path)? else {
+            return Self::from_toml_str(&fs::read_to_string(path)?)
+        };
+        let table = read_toml_table(path)?.unwrap_or_default();
+
+        Ok(Self::layer_over_vendor(vendor_table, table)?.0)
+    }
+
+    /// Migrates the settings file at `path` layered over the vendor defaults at `vendor_path`. If there's no settings
+    /// file but there are vendor defaults, there's nothing to migrate.
+    fn migrate_layered_file(vendor_path: &Path, path: &Path) -> Result<bool, StorageError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound && fs::exists(vendor_path)? => return Ok(false),
+            Err(err) => return Err(err.into())
+        };
+        let Some(vendor_table) = read_toml_table(vendor_path)? else {
+            let mut config = toml::from_str::<Self>(&contents)?;
+            let migrated = config.migrate();
+            if migrated {
+                fs::write(path, config.to_toml_str()?)?;
+            }
+
+            return Ok(migrated)
+        };
+
+        let table = toml::from_str::<toml::Table>(&contents)?;
+        let (config, migrated) = Self::layer_over_vendor(vendor_table.clone(), table.clone())?;
+        if migrated {
+            fs::write(path, config.to_layered_toml_str(vendor_table, &table)?)?;
+        }
+
+        Ok(migrated)
+    }
+
+    /// Writes the settings file at `path` from the settings file at `custom_path`, or from the defaults if it's not
+    /// provided, in which case the file must not exist yet. With vendor defaults at `vendor_path`, the provided file
+    /// is layered over them, and only the keys that differ from them are written, so that the vendor defaults still
+    /// apply to everything else.
+    fn init_layered(vendor_path: &Path, path: &Path, custom_path: Option<PathBuf>) -> Result<(), StorageError> {
+        let custom_contents = match custom_path {
+            Some(custom_path) => Some(fs::read_to_string(custom_path)?),
+            None if fs::exists(path)? => return Err(StorageError::FileAlreadyExists),
+            None => None
+        };
+
+        let contents = match (read_toml_table(vendor_path)?, custom_contents) {
+            (Some(vendor_table), custom_contents) => {
+                let table = custom_contents.map(|contents| toml::from_str::<toml::Table>(&contents)).transpose()?;
+                let (config, _) = Self::layer_over_vendor(vendor_table.clone(), table.unwrap_or_default())?;
+                config.to_layered_toml_str(vendor_table, &toml::Table::new())?
+            },
+            (None, Some(custom_contents)) => Self::from_toml_str(&custom_contents)?.to_toml_str()?,
+            (None, None) => Self::new().to_toml_str()?
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+
+    /// Serializes the settings that differ from the `vendor_table` or are in `existing_table`, along with the
+    /// `config_version`, for a settings file that's layered over the vendor defaults
+    fn to_layered_toml_str(&self, vendor_table: toml::Table, existing_table: &toml::Table) -> Result<String, StorageError> {
+        // Settings that are left out of both files take their default values, so those don't need to be written
+        let mut base_table = toml::from_str::<toml::Table>(&Self::default().to_toml_str()?)?;
+        merge_toml_tables(&mut base_table, vendor_table);
+
+        let table = toml::from_str::<toml::Table>(&self.to_toml_str()?)?;
+        let mut overrides = get_toml_overrides(&base_table, table, existing_table);
+        overrides.insert(String::from("config_version"), toml::Value::Integer(i64::from(self.config_version)));
+
+        Ok(toml::to_string(&overrides)?)
+    }
+
+    /// Merges the settings `table` over the `vendor_table` and migrates the result. The settings file's own
+    /// `config_version` decides the migration, so that a newer vendor file doesn't hide an old settings file. A
+    /// settings file without one is from before versioning, unless it's empty or missing. Returns the settings and
+    /// whether they were migrated.
+    fn layer_over_vendor(mut vendor_table: toml::Table, table: toml::Table) -> Result<(Self, bool), StorageError> {
+        let version = match table.get("config_version") {
+            Some(version) => Some(version.clone()),
+            None if table.is_empty() => None,
+            None => Some(toml::Value::Integer(0))
+        };
+
+        merge_toml_tables(&mut vendor_table, table);
+        if let Some(version) = version {
+            vendor_table.insert(String::from("config_version"), version);
+        }
+
+        let mut config = toml::Value::Table(vendor_table).try_into::<Self>()?;
+        let migrated = config.migrate();
+        Ok((config, migrated))
+    }
+}
+
 impl TomlStorage for Config {
+    /// The default settings, set up for the package manager that's installed if it can be found
+    fn new() -> Self {
+        let mut config = Config::default();
+        config.package.package_manager = PackageManagerType::detect();
+        config.fill_default_commands();
+        config
+    }
+
+    /// Reads the settings file layered over the vendor defaults in `/usr/lib/package-assistant/settings.toml`, if
+    /// a distribution ships them. Every key in the settings file takes precedence over the vendor default, including
+    /// the keys within `[service]` and `[package]`, so the settings file only needs the keys that differ. Either file
+    /// may be missing as long as the other one exists.
+    fn fetch() -> Result<Self, StorageError> {
+        Self::fetch_layered(&Path::new(VENDOR_CONFIG_PATH).join(CONFIG_FILE_NAME), &Self::get_file_path()?)
+    }
+
+    /// Migrates the settings file in the current format. With vendor defaults, the file is migrated as part of the
+    /// layered settings, and only the keys that differ from the vendor defaults or were already in the file are
+    /// written back, so that the vendor defaults aren't copied into it.
+    fn migrate_file() -> Result<bool, StorageError> {
+        Self::migrate_layered_file(&Path::new(VENDOR_CONFIG_PATH).join(CONFIG_FILE_NAME), &Self::get_file_path()?)
+    }
+
+    /// Writes the settings file from `custom_path` or the defaults. If a distribution ships vendor defaults, the file
+    /// only gets the keys that differ from them.
+    fn init(custom_path: Option<PathBuf>) -> Result<PathBuf, StorageError> {
+        let path = Self::get_file_path()?;
+        Self::init_layered(&Path::new(VENDOR_CONFIG_PATH).join(CONFIG_FILE_NAME), &path, custom_path)?;
+        Ok(path)
+    }
+
+    /// Uses the system-wide configuration in `/etc/package-assistant` if it's usable, and otherwise falls back to the
+    /// user's configuration directory, so that unprivileged users can have their own settings
+    fn get_dir_path() -> Result<PathBuf, StorageError> {
+        match get_system_dir_path(Path::new("/")) {
+            Some(system_dir) => Ok(system_dir),
+            None => get_xdg_dir_path(Self::directory_env_var(), Self::default_directory())
+        }
+    }
+
     fn default_directory() -> &'static str {
         DEFAULT_CONFIG_PATH
     }
@@ -25,6 +242,16 @@ impl TomlStorage for Config {
     fn file_name() -> &'static str {
         CONFIG_FILE_NAME
     }
+
+    fn migrate(&mut self) -> bool {
+        let old_version = self.config_version;
+        if self.config_version < 1 {
+            self.migrate_v0();
+            self.config_version = 1;
+        }
+
+        self.config_version != old_version
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -33,17 +260,188 @@ pub struct ServiceConfig {
     pub update_check_frequency: u32,
     pub download_in_background: bool,
     pub update_on_reboot: bool,
+    /// Minutes after refreshing the repositories during which update checks use the cached metadata instead
+    pub repo_refresh_age: Option<u32>
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct PackageConfig {
     pub package_manager: Option<PackageManagerType>,
+    /// Command that checks for updates instead of the package manager's own check, e.g. a wrapper that sets up a
+    /// proxy. It has to print the same output as `zypper --xmlout lu` or `dnf check-update`, and runs regardless of
+    /// whether the repositories would be refreshed.
+    pub check_update_command: Option<String>,
     pub download_command: String,
     pub update_command: String,
     pub noconfirm_update_command: String,
-    pub cached_package_path: Option<PathBuf>
+    /// Command for a distribution upgrade, e.g. `zypper dup`, which unlike a regular update may change
+    /// vendors, downgrade or remove packages. Falls back to the package manager's default.
+    pub dist_upgrade_command: Option<String>,
+    pub noconfirm_dist_upgrade_command: Option<String>,
+    /// Command that prepares an update to be applied on the next reboot. Falls back to the package manager's default.
+    pub offline_update_command: Option<String>,
+    /// Whether dnf is dnf5 rather than dnf4. Detected from `dnf --version` if unset.
+    pub dnf5: Option<bool>,
+    /// Command that runs before installing updates, e.g. to take a backup. The update is aborted if it fails.
+    pub pre_update_command: Option<String>,
+    /// Command that runs after updates are installed successfully, e.g. `flatpak update`
+    pub post_update_command: Option<String>,
+    /// Creates a snapshot before installing updates, so there's a restore point if an update breaks the system
+    #[serde(default)]
+    pub snapshot_before_update: bool,
+    /// Command that creates the snapshot and prints its number. Falls back to `snapper create`.
+    pub snapshot_command: Option<String>,
+    /// Also installs patches that require a reboot when updating without confirmation, which zypper skips by default.
+    /// The system may need to be rebooted afterwards.
+    #[serde(default)]
+    pub include_reboot_patches: bool,
+    /// Directory of the downloaded packages, or a list of directories. A package that's in more than one of them is
+    /// only read from its newest copy.
+    pub cached_package_path: Option<PathList>,
+    /// Uses sudo instead of pkexec when there's no terminal or graphical session for pkexec to ask for a password in
+    #[serde(default)]
+    pub sudo_fallback: bool,
+    /// Package name patterns that are never updated, e.g. `kernel*`
+    #[serde(default)]
+    pub ignored_packages: Vec<String>,
+    /// Package name patterns whose update requires a reboot. Only used when the package manager can't tell
+    /// whether a reboot is required.
+    #[serde(default = "default_reboot_triggers")]
+    pub reboot_triggers: Vec<String>,
+    /// Path of the rpm binary, for systems where it isn't in PATH. Looked up in PATH if unset.
+    pub rpm_path: Option<PathBuf>,
+    /// Path of the package manager's binary, e.g. `/opt/zypper/bin/zypper`, for the commands that package-assistant
+    /// runs itself. The commands from settings run as they're written. Looked up in PATH if unset.
+    pub package_manager_path: Option<PathBuf>,
+    /// Proxy for HTTP connections of the spawned commands, e.g. `http://proxy.example.com:3128`. Overrides the
+    /// `http_proxy` environment variable, which is used as is if this is unset.
+    pub http_proxy: Option<String>,
+    /// Proxy for HTTPS connections of the spawned commands. Overrides the `https_proxy` environment variable.
+    pub https_proxy: Option<String>,
+    /// Comma-separated hosts that are connected to without the proxy. Overrides the `no_proxy` environment variable.
+    pub no_proxy: Option<String>,
+    /// Locale that spawned commands run with, e.g. `C.UTF-8`, so that their output isn't localized
+    pub output_locale: Option<String>,
+    /// Header printed above each package's changelogs, where `{name}` is replaced by the package name
+    #[serde(default = "default_changelog_header_format")]
+    pub changelog_header_format: String,
+    /// Printed between the changelogs of consecutive packages
+    #[serde(default = "default_changelog_separator")]
+    pub changelog_separator: String,
+    /// strftime format of the date above each changelog entry
+    #[serde(default = "default_changelog_date_format")]
+    pub changelog_date_format: String,
+    /// Timezone of the changelog dates: `local` (the default), `UTC`, or a fixed offset such as `+02:00`
+    pub changelog_timezone: Option<String>,
+    /// Leaves out the changelog entries of the installed versions and older ones. Turning this off is useful for a
+    /// cache of packages for other machines, where the installed versions don't apply.
+    #[serde(default = "default_filter_changelogs_by_installed")]
+    pub filter_changelogs_by_installed: bool,
+    /// Number of typos allowed in changelog queries when fuzzy matching is enabled
+    #[serde(default = "default_fuzzy_max_distance")]
+    pub fuzzy_max_distance: usize,
+    /// Maximum number of threads reading packages while scanning for changelogs. Zero or unset means one per CPU.
+    pub scan_threads: Option<usize>
+}
+
+/// Gets the system-wide configuration directory below `root` if its settings file can be read, or if it doesn't exist
+/// yet and the directory can be written to, e.g. as root
+fn get_system_dir_path(root: &Path) -> Option<PathBuf> {
+    let system_dir = root.join(SYSTEM_CONFIG_PATH);
+    let system_file = system_dir.join(CONFIG_FILE_NAME);
+
+    let use_system_dir = if system_file.exists() {
+        access(&system_file, AccessFlags::R_OK).is_ok()
+    } else {
+        // The directory is created by init if needed, in which case its parent needs to be writable
+        let writable_dir = if system_dir.exists() { system_dir.clone() } else { root.join(SYSTEM_CONFIG_PARENT_PATH) };
+        access(&writable_dir, AccessFlags::W_OK).is_ok()
+    };
+
+    use_system_dir.then_some(system_dir)
+}
+
+/// Parses the TOML file at `path` as a table, or returns `None` if it doesn't exist
+fn read_toml_table(path: &Path) -> Result<Option<toml::Table>, StorageError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(toml::from_str(&contents)?)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into())
+    }
 }
 
+/// Gets the values of `table` that differ from those in `vendor_table` or are in `existing_table`, which layered over
+/// `vendor_table` give `table` again
+fn get_toml_overrides(vendor_table: &toml::Table, table: toml::Table, existing_table: &toml::Table) -> toml::Table {
+    let empty_table = toml::Table::new();
+
+    table.into_iter()
+        .filter_map(|(key, value)| match (vendor_table.get(&key), value) {
+            (Some(toml::Value::Table(vendor_subtable)), toml::Value::Table(subtable)) => {
+                let existing_subtable = existing_table.get(&key).and_then(toml::Value::as_table).unwrap_or(&empty_table);
+                let overrides = get_toml_overrides(vendor_subtable, subtable, existing_subtable);
+                (!overrides.is_empty() || existing_table.contains_key(&key)).then_some((key, toml::Value::Table(overrides)))
+            },
+            (Some(vendor_value), value) if *vendor_value == value && !existing_table.contains_key(&key) => None,
+            (_, value) => Some((key, value))
+        })
+        .collect()
+}
+
+/// Overwrites the values in `base` with those in `overrides`, merging tables that are in both key by key
+fn merge_toml_tables(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(table)) => merge_toml_tables(base_table, table),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn default_changelog_header_format() -> String {
+    String::from("==== {name} ====")
+}
+
+fn default_changelog_separator() -> String {
+    String::from("\n\n")
+}
+
+fn default_changelog_date_format() -> String {
+    String::from(DEFAULT_CHANGELOG_DATE_FORMAT)
+}
+
+fn default_filter_changelogs_by_installed() -> bool {
+    true
+}
+
+fn default_fuzzy_max_distance() -> usize {
+    2
+}
+
+fn default_reboot_triggers() -> Vec<String> {
+    vec![String::from("kernel*"), String::from("glibc"), String::from("systemd")]
+}
+
+/// Either a single path or a list of paths in settings
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PathList {
+    Single(PathBuf),
+    Multiple(Vec<PathBuf>)
+}
+
+impl PathList {
+    pub fn paths(&self) -> &[PathBuf] {
+        match self {
+            PathList::Single(path) => std::slice::from_ref(path),
+            PathList::Multiple(paths) => paths
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum PackageManagerType {
     Zypper,
     Dnf,
@@ -51,49 +449,345 @@ pub enum PackageManagerType {
     Pacman
 }
 
+impl PackageManagerType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PackageManagerType::Zypper => "zypper",
+            PackageManagerType::Dnf => "dnf",
+            PackageManagerType::Apt => "apt",
+            PackageManagerType::Pacman => "pacman"
+        }
+    }
+
+    /// Finds the installed package manager by looking for its binary in PATH
+    pub fn detect() -> Option<Self> {
+        let path = std::env::var_os("PATH")?;
+        let dirs = std::env::split_paths(&path).collect::<Vec<PathBuf>>();
+
+        [PackageManagerType::Zypper, PackageManagerType::Dnf, PackageManagerType::Apt, PackageManagerType::Pacman]
+            .into_iter()
+            .find(|package_manager| dirs.iter().any(|dir| dir.join(package_manager.binary_name()).is_file()))
+    }
+
+    fn binary_name(&self) -> &'static str {
+        match self {
+            PackageManagerType::Apt => "apt-get",
+            package_manager => package_manager.as_str()
+        }
+    }
+
+    /// The download, interactive update and non-interactive update commands that suit this package manager
+    fn default_commands(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            PackageManagerType::Zypper => ("zypper dup -dy", "zypper dup", "zypper dup -y"),
+            PackageManagerType::Dnf => ("dnf upgrade --downloadonly -y", "dnf upgrade", "dnf upgrade -y"),
+            PackageManagerType::Apt => ("apt-get update && apt-get upgrade -y --download-only", "apt-get upgrade", "apt-get upgrade -y"),
+            PackageManagerType::Pacman => ("pacman -Syuw --noconfirm", "pacman -Syu", "pacman -Syu --noconfirm")
+        }
+    }
+}
+
 impl Serialize for PackageManagerType {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where S: serde::Serializer {
-        match self {
-            PackageManagerType::Zypper => serializer.serialize_str("zypper"),
-            PackageManagerType::Dnf => serializer.serialize_str("dnf"),
-            PackageManagerType::Apt => serializer.serialize_str("apt"),
-            PackageManagerType::Pacman => serializer.serialize_str("pacman")
-        }
+        serializer.serialize_str(self.as_str())
     }
 }
 
-impl<'de> Deserialize<'de> for PackageManagerType {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where D: serde::Deserializer<'de> {
-        let s = String::deserialize(deserializer)?;
+impl FromStr for PackageManagerType {
+    type Err = String;
 
-        match s.as_str() {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
             "zypper" => Ok(PackageManagerType::Zypper),
             "dnf" => Ok(PackageManagerType::Dnf),
             "apt" => Ok(PackageManagerType::Apt),
             "pacman" => Ok(PackageManagerType::Pacman),
-            _ => Err(Error::custom("'package_manager' must be set to either \"zypper\", \"dnf\", \"apt\", or \"pacman\" in settings"))
+            _ => Err(String::from("'package_manager' must be set to either \"zypper\", \"dnf\", \"apt\", or \"pacman\" in settings"))
         }
     }
 }
 
+impl<'de> Deserialize<'de> for PackageManagerType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: serde::Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(Error::custom)
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
+            config_version: CURRENT_CONFIG_VERSION,
             service: ServiceConfig {
                 enable_service: true,
                 update_check_frequency: 30,
                 download_in_background: true,
-                update_on_reboot: true
+                update_on_reboot: true,
+                repo_refresh_age: None
             },
             package: PackageConfig {
                 package_manager: None,
+                check_update_command: None,
                 download_command: String::from(""),
                 update_command: String::from(""),
                 noconfirm_update_command: String::from(""),
-                cached_package_path: None
+                dist_upgrade_command: None,
+                noconfirm_dist_upgrade_command: None,
+                offline_update_command: None,
+                dnf5: None,
+                pre_update_command: None,
+                post_update_command: None,
+                snapshot_before_update: false,
+                snapshot_command: None,
+                include_reboot_patches: false,
+                cached_package_path: None,
+                sudo_fallback: false,
+                ignored_packages: Vec::new(),
+                reboot_triggers: default_reboot_triggers(),
+                rpm_path: None,
+                package_manager_path: None,
+                http_proxy: None,
+                https_proxy: None,
+                no_proxy: None,
+                output_locale: None,
+                changelog_header_format: default_changelog_header_format(),
+                changelog_separator: default_changelog_separator(),
+                changelog_date_format: default_changelog_date_format(),
+                changelog_timezone: None,
+                filter_changelogs_by_installed: default_filter_changelogs_by_installed(),
+                fuzzy_max_distance: default_fuzzy_max_distance(),
+                scan_threads: None
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    const VENDOR_SETTINGS: &str = r#"
+config_version = 1
+
+[service]
+enable_service = true
+update_check_frequency = 30
+download_in_background = true
+update_on_reboot = false
+
+[package]
+package_manager = "dnf"
+download_command = "dnf upgrade --downloadonly -y"
+update_command = "dnf upgrade"
+noconfirm_update_command = "dnf upgrade -y"
+"#;
+
+    /// A directory for the settings files of one test, which is removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("package-assistant-config-test-{}-{}", std::process::id(), name));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, file_name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(file_name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn vendor_settings_are_used_without_settings_file() {
+        let dir = TestDir::new("vendor-only");
+        let vendor_path = dir.write("vendor.toml", VENDOR_SETTINGS);
+
+        let config = Config::fetch_layered(&vendor_path, &dir.0.join("settings.toml")).unwrap();
+        assert!(config.package.package_manager == Some(PackageManagerType::Dnf));
+        assert_eq!(config.package.update_command, "dnf upgrade");
+        assert!(!config.service.update_on_reboot);
+    }
+
+    #[test]
+    fn settings_file_is_used_without_vendor_settings() {
+        let dir = TestDir::new("etc-only");
+        let path = dir.write("settings.toml", &VENDOR_SETTINGS.replace("\"dnf upgrade\"", "\"dnf upgrade --refresh\""));
+
+        let config = Config::fetch_layered(&dir.0.join("vendor.toml"), &path).unwrap();
+        assert_eq!(config.package.update_command, "dnf upgrade --refresh");
+    }
+
+    #[test]
+    fn partial_settings_file_is_required_without_vendor_settings() {
+        let dir = TestDir::new("etc-only-partial");
+        let path = dir.write("settings.toml", "[service]\nupdate_check_frequency = 60\n");
+
+        assert!(matches!(Config::fetch_layered(&dir.0.join("vendor.toml"), &path), Err(StorageError::TomlDeserializationError(_))));
+    }
+
+    #[test]
+    fn settings_file_overrides_vendor_settings_key_by_key() {
+        let dir = TestDir::new("layered");
+        let vendor_path = dir.write("vendor.toml", VENDOR_SETTINGS);
+        let path = dir.write("settings.toml", "config_version = 1\n\n[service]\nupdate_check_frequency = 60\n");
+
+        let config = Config::fetch_layered(&vendor_path, &path).unwrap();
+        assert_eq!(config.service.update_check_frequency, 60);
+        assert!(config.service.enable_service);
+        assert_eq!(config.package.update_command, "dnf upgrade");
+    }
+
+    #[test]
+    fn unversioned_settings_file_is_migrated_over_newer_vendor_settings() {
+        let dir = TestDir::new("layered-v0");
+        let vendor_path = dir.write("vendor.toml", &VENDOR_SETTINGS.replace("\"dnf upgrade --downloadonly -y\"", "\"\""));
+        let path = dir.write("settings.toml", "[service]\nupdate_check_frequency = 60\n");
+
+        let config = Config::fetch_layered(&vendor_path, &path).unwrap();
+        assert_eq!(config.config_version, CURRENT_CONFIG_VERSION);
+        assert_eq!(config.package.download_command, "dnf upgrade --downloadonly -y");
+    }
+
+    #[test]
+    fn partial_settings_file_is_migrated_without_copying_vendor_settings() {
+        let dir = TestDir::new("migrate-partial");
+        let vendor_path = dir.write("vendor.toml", VENDOR_SETTINGS);
+        let path = dir.write("settings.toml", "[service]\nupdate_check_frequency = 60\n");
+
+        assert!(Config::migrate_layered_file(&vendor_path, &path).unwrap());
+        let table = toml::from_str::<toml::Table>(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(table["config_version"].as_integer(), Some(i64::from(CURRENT_CONFIG_VERSION)));
+        assert_eq!(table["service"]["update_check_frequency"].as_integer(), Some(60));
+        assert!(!table.contains_key("package"));
+
+        assert!(!Config::migrate_layered_file(&vendor_path, &path).unwrap());
+    }
+
+    #[test]
+    fn unversioned_settings_file_is_migrated_without_vendor_settings() {
+        let dir = TestDir::new("standalone-v0");
+        let vendor_path = dir.0.join("vendor.toml");
+        let path = dir.write("settings.toml", r#"
+[service]
+enable_service = true
+update_check_frequency = 30
+download_in_background = true
+update_on_reboot = false
+
+[package]
+package_manager = "zypper"
+download_command = ""
+update_command = "zypper update --no-recommends"
+noconfirm_update_command = ""
+"#);
+        let (download_command, _, noconfirm_update_command) = PackageManagerType::Zypper.default_commands();
+
+        let config = Config::fetch_layered(&vendor_path, &path).unwrap();
+        assert_eq!(config.config_version, 1);
+        assert_eq!(config.package.download_command, download_command);
+        assert_eq!(config.package.update_command, "zypper update --no-recommends");
+        assert_eq!(config.package.noconfirm_update_command, noconfirm_update_command);
+
+        assert!(Config::migrate_layered_file(&vendor_path, &path).unwrap());
+        let migrated = Config::from_toml_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(migrated.config_version, 1);
+        assert_eq!(migrated.package.download_command, download_command);
+        assert!(!Config::migrate_layered_file(&vendor_path, &path).unwrap());
+    }
+
+    #[test]
+    fn missing_settings_file_has_nothing_to_migrate_with_vendor_settings() {
+        let dir = TestDir::new("migrate-vendor-only");
+        let vendor_path = dir.write("vendor.toml", VENDOR_SETTINGS);
+        let path = dir.0.join("settings.toml");
+
+        assert!(!Config::migrate_layered_file(&vendor_path, &path).unwrap());
+        assert!(!path.exists());
+
+        // Without either file, the settings file is still required
+        let result = Config::migrate_layered_file(&dir.0.join("missing.toml"), &path);
+        assert!(matches!(result, Err(StorageError::IO(_))));
+    }
+
+    #[test]
+    fn init_only_writes_settings_that_differ_from_vendor_settings() {
+        let dir = TestDir::new("init-layered");
+        let vendor_path = dir.write("vendor.toml", VENDOR_SETTINGS);
+        let custom_path = dir.write("custom.toml", &VENDOR_SETTINGS.replace("update_check_frequency = 30", "update_check_frequency = 60"));
+        let path = dir.0.join("etc/settings.toml");
+
+        Config::init_layered(&vendor_path, &path, Some(custom_path)).unwrap();
+        let table = toml::from_str::<toml::Table>(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(table["config_version"].as_integer(), Some(i64::from(CURRENT_CONFIG_VERSION)));
+        assert_eq!(table["service"].as_table().unwrap().len(), 1);
+        assert_eq!(table["service"]["update_check_frequency"].as_integer(), Some(60));
+        assert!(!table.contains_key("package"));
+
+        let config = Config::fetch_layered(&vendor_path, &path).unwrap();
+        assert_eq!(config.service.update_check_frequency, 60);
+        assert_eq!(config.package.update_command, "dnf upgrade");
+
+        // The defaults are the vendor settings, so nothing else is written, and an existing file isn't replaced
+        fs::remove_file(&path).unwrap();
+        Config::init_layered(&vendor_path, &path, None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().trim(), format!("config_version = {}", CURRENT_CONFIG_VERSION));
+        assert!(matches!(Config::init_layered(&vendor_path, &path, None), Err(StorageError::FileAlreadyExists)));
+    }
+
+    #[test]
+    fn init_writes_whole_settings_file_without_vendor_settings() {
+        let dir = TestDir::new("init-standalone");
+        let custom_path = dir.write("custom.toml", VENDOR_SETTINGS);
+        let path = dir.0.join("settings.toml");
+
+        Config::init_layered(&dir.0.join("vendor.toml"), &path, Some(custom_path)).unwrap();
+        let config = Config::from_toml_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.config_version, CURRENT_CONFIG_VERSION);
+        assert_eq!(config.package.update_command, "dnf upgrade");
+        assert!(fs::read_to_string(&path).unwrap().contains("update_check_frequency"));
+    }
+
+    #[test]
+    fn readable_system_settings_are_used() {
+        let root = TestDir::new("system-settings");
+        fs::create_dir_all(root.0.join(SYSTEM_CONFIG_PATH)).unwrap();
+        root.write(&format!("{}/{}", SYSTEM_CONFIG_PATH, CONFIG_FILE_NAME), VENDOR_SETTINGS);
+
+        assert_eq!(get_system_dir_path(&root.0), Some(root.0.join("etc/package-assistant")));
+    }
+
+    #[test]
+    fn system_dir_is_used_without_settings_if_it_can_be_created() {
+        let root = TestDir::new("system-writable");
+        fs::create_dir_all(root.0.join(SYSTEM_CONFIG_PARENT_PATH)).unwrap();
+
+        assert_eq!(get_system_dir_path(&root.0), Some(root.0.join("etc/package-assistant")));
+    }
+
+    #[test]
+    fn user_dir_is_used_if_system_settings_cant_be_read_or_created() {
+        let root = TestDir::new("system-unusable");
+        // Without an etc directory, there's nowhere to create the system-wide settings
+        assert_eq!(get_system_dir_path(&root.0), None);
+
+        // Root can read the settings regardless of their permissions
+        if !nix::unistd::geteuid().is_root() {
+            fs::create_dir_all(root.0.join(SYSTEM_CONFIG_PATH)).unwrap();
+            let path = root.write(&format!("{}/{}", SYSTEM_CONFIG_PATH, CONFIG_FILE_NAME), VENDOR_SETTINGS);
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o000)).unwrap();
+            assert_eq!(get_system_dir_path(&root.0), None);
+        }
+    }
+}
@@ -6,9 +6,30 @@ const DATA_HOME: &str = "XDG_DATA_HOME";
 const DEFAULT_DATA_PATH: &str = ".local/share";
 const DATA_FILE_NAME: &str = "data.toml";
 
-#[derive(Deserialize, Serialize)]
+#[derive(Default, Deserialize, Serialize)]
 pub struct Data {
-    pub update_timestamp: u64
+    pub update_timestamp: u64,
+    /// Unix timestamp of when the repositories were last refreshed while checking for updates
+    #[serde(default)]
+    pub last_refresh_timestamp: u64,
+    #[serde(default)]
+    pub download_status: DownloadStatus,
+    /// Unix timestamp of when the last download finished
+    #[serde(default)]
+    pub download_timestamp: u64,
+    /// Number of the snapshot created before the last update
+    #[serde(default)]
+    pub snapshot_number: Option<u32>
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadStatus {
+    #[default]
+    None,
+    Running,
+    Succeeded,
+    Failed
 }
 
 impl TomlStorage for Data {
@@ -25,10 +46,43 @@ impl TomlStorage for Data {
     }
 }
 
-impl Default for Data {
-    fn default() -> Self {
-        Self {
-            update_timestamp: 0
-        }
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn data_is_saved_to_missing_directory() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-data-test-{}", std::process::id()));
+        let path = dir.join("package-assistant").join(DATA_FILE_NAME);
+        let data = Data { last_refresh_timestamp: 1704110400, ..Default::default() };
+
+        let result = Data::save_to(data, &path);
+        let contents = fs::read_to_string(&path);
+        let _ = fs::remove_dir_all(&dir);
+
+        result.unwrap();
+        assert_eq!(Data::from_toml_str(&contents.unwrap()).unwrap().last_refresh_timestamp, 1704110400);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn last_refresh_defaults_to_zero_in_old_data() {
+        let data = Data::from_toml_str("update_timestamp = 1704110400").unwrap();
+        assert_eq!(data.last_refresh_timestamp, 0);
+    }
+
+    #[test]
+    fn missing_data_defaults_but_invalid_data_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-data-test-{}-invalid", std::process::id()));
+        let path = dir.join(DATA_FILE_NAME);
+        let missing = Data::fetch_or_default_from(&path);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "update_timestamp = \"yesterday\"").unwrap();
+        let invalid = Data::fetch_or_default_from(&path);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(missing.unwrap().update_timestamp, 0);
+        assert!(matches!(invalid, Err(crate::storage::Error::TomlDeserializationError(_))));
+    }
+}
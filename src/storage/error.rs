@@ -1,14 +1,33 @@
 use std::io;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     DirUndefined,
     FileAlreadyExists,
+    AlreadyRunning,
+    /// Settings that can be parsed but can't be used, along with every problem that was found
+    InvalidConfig(Vec<String>),
     TomlDeserializationError(toml::de::Error),
     TomlSerializationError(toml::ser::Error),
     IO(io::Error),
 }
 
+impl Error {
+    /// A stable identifier of the kind of error for scripts, which unlike the message doesn't change between versions
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::DirUndefined => "DIR_UNDEFINED",
+            Error::FileAlreadyExists => "FILE_ALREADY_EXISTS",
+            Error::AlreadyRunning => "ALREADY_RUNNING",
+            Error::InvalidConfig(_) => "INVALID_CONFIG",
+            Error::TomlDeserializationError(_) => "INVALID_TOML",
+            Error::TomlSerializationError(_) => "TOML_SERIALIZATION",
+            Error::IO(_) => "IO"
+        }
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
@@ -43,9 +62,41 @@ impl std::fmt::Display for Error {
         match self {
             Error::DirUndefined => write!(f, "could not determine a directory to store data"),
             Error::FileAlreadyExists => write!(f, "file already exists"),
+            Error::AlreadyRunning => write!(f, "another package-assistant process is already updating"),
+            Error::InvalidConfig(problems) => write!(f, "invalid settings: {}", problems.join("; ")),
             Error::IO(err) => err.fmt(f),
             Error::TomlDeserializationError(err) => err.fmt(f),
             Error::TomlSerializationError(err) => err.fmt(f),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn every_error_has_a_distinct_code() {
+        let errors = [
+            Error::DirUndefined,
+            Error::FileAlreadyExists,
+            Error::AlreadyRunning,
+            Error::InvalidConfig(Vec::new()),
+            Error::TomlDeserializationError(toml::from_str::<toml::Table>("x").unwrap_err()),
+            Error::TomlSerializationError(toml::to_string(&1).unwrap_err()),
+            Error::IO(io::Error::other("test"))
+        ];
+        // Fails to compile when a variant is added, so that it's added above too
+        let codes = errors.iter()
+            .map(|err| match err {
+                Error::DirUndefined | Error::FileAlreadyExists | Error::AlreadyRunning | Error::InvalidConfig(_)
+                | Error::TomlDeserializationError(_) | Error::TomlSerializationError(_) | Error::IO(_) => err.code()
+            })
+            .collect::<HashSet<&str>>();
+
+        assert_eq!(codes.len(), errors.len());
+        assert!(codes.iter().all(|code| !code.is_empty()));
+    }
+}
@@ -0,0 +1,110 @@
+use std::fs::{self, File, TryLockError};
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use nix::errno::Errno;
+use nix::sys::signal;
+use nix::unistd::Pid;
+
+use super::data::Data;
+use super::error::Error;
+use super::toml::TomlStorage;
+
+const LOCK_FILE_NAME: &str = "update.lock";
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// An advisory lock that prevents package-assistant from running multiple package manager
+/// operations at once. The lock is released when this is dropped, unless it's kept for a
+/// command that's still running with `keep_while_running`.
+pub struct UpdateLock {
+    file: File
+}
+
+impl UpdateLock {
+    /// Locks the lock file in the data directory, creating it if needed. Returns
+    /// `Error::AlreadyRunning` if another process is holding the lock.
+    pub fn acquire() -> Result<Self> {
+        Self::acquire_in(&Data::get_dir_path()?)
+    }
+
+    /// Locks the lock file in `dir`, creating both if needed
+    fn acquire_in(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE_NAME);
+
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => return Err(Error::AlreadyRunning),
+            Err(TryLockError::Error(err)) => return Err(Error::IO(err))
+        }
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        if contents.trim().parse::<i32>().is_ok_and(is_process_running) {
+            return Err(Error::AlreadyRunning)
+        }
+
+        file.set_len(0)?;
+        Ok(Self { file })
+    }
+
+    /// Keeps the lock after it's released until the process `pid` exits, e.g. for a package manager that was left
+    /// running in the middle of a transaction after timing out. The process id is written to the lock file, so if
+    /// it's reused by another process after the package manager exits, updates are blocked until that one exits too.
+    pub fn keep_while_running(&self, pid: u32) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.write_all_at(pid.to_string().as_bytes(), 0)?;
+        Ok(())
+    }
+}
+
+/// Checks whether the process `pid` exists, including processes of other users, such as root
+fn is_process_running(pid: i32) -> bool {
+    pid > 0 && matches!(signal::kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_acquisition_fails_while_first_is_held() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-lock-test-{}", std::process::id()));
+
+        let lock = UpdateLock::acquire_in(&dir).unwrap();
+        assert!(matches!(UpdateLock::acquire_in(&dir), Err(Error::AlreadyRunning)));
+
+        drop(lock);
+        assert!(UpdateLock::acquire_in(&dir).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lock_is_kept_while_left_running_command_runs() {
+        let dir = std::env::temp_dir().join(format!("package-assistant-lock-test-{}-kept", std::process::id()));
+        let mut child = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+
+        let lock = UpdateLock::acquire_in(&dir).unwrap();
+        lock.keep_while_running(child.id()).unwrap();
+        drop(lock);
+        let while_running = UpdateLock::acquire_in(&dir);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let after_exit = UpdateLock::acquire_in(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(while_running, Err(Error::AlreadyRunning)));
+        assert!(after_exit.is_ok());
+    }
+}
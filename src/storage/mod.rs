@@ -2,8 +2,12 @@ mod error;
 mod toml;
 mod config;
 mod data;
+mod lock;
+mod schema;
 
 pub use error::Error;
 pub use toml::*;
 pub use config::*;
-pub use data::*;
\ No newline at end of file
+pub use data::*;
+pub use lock::*;
+pub use schema::get_config_schema;
\ No newline at end of file
@@ -0,0 +1,136 @@
+use serde_json::{json, Map, Value};
+
+use super::config::{CURRENT_CONFIG_VERSION, DEFAULT_CHANGELOG_DATE_FORMAT};
+
+/// Builds a JSON Schema of the settings file, for validating it in editors and CI. This needs to be kept in sync
+/// with `Config` by hand whenever a setting is added or changed, which the tests check. No key is required, since a settings file that's
+/// layered over vendor defaults only needs the keys that differ from them.
+pub fn get_config_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "package-assistant settings",
+        "type": "object",
+        "properties": {
+            "config_version": with_default(typed("integer", "Version of the settings format. Older versions are migrated when the settings are read."), json!(CURRENT_CONFIG_VERSION)),
+            "service": object([
+                ("enable_service", typed("boolean", "Whether the systemd services are enabled")),
+                ("update_check_frequency", typed("integer", "Minutes between update checks")),
+                ("download_in_background", typed("boolean", "Downloads updates in a separate process after checking for them")),
+                ("update_on_reboot", typed("boolean", "Installs downloaded updates on the next reboot")),
+                ("repo_refresh_age", typed("integer", "Minutes after refreshing the repositories during which update checks use the cached metadata instead"))
+            ]),
+            "package": object([
+                ("package_manager", json!({
+                    "type": "string",
+                    "enum": ["zypper", "dnf", "apt", "pacman"],
+                    "description": "The system's package manager"
+                })),
+                ("check_update_command", typed("string", "Command that checks for updates instead of the package manager's own check. It has to print the same output as `zypper --xmlout lu` or `dnf check-update`.")),
+                ("download_command", typed("string", "Command that downloads the available updates without installing them")),
+                ("update_command", typed("string", "Command that installs the available updates interactively")),
+                ("noconfirm_update_command", typed("string", "Command that installs the available updates without asking for confirmation")),
+                ("dist_upgrade_command", typed("string", "Command for a distribution upgrade, e.g. `zypper dup`. Falls back to the package manager's default.")),
+                ("noconfirm_dist_upgrade_command", typed("string", "Command for a distribution upgrade without asking for confirmation")),
+                ("offline_update_command", typed("string", "Command that prepares an update to be applied on the next reboot")),
+                ("dnf5", typed("boolean", "Whether dnf is dnf5 rather than dnf4. Detected from `dnf --version` if unset.")),
+                ("pre_update_command", typed("string", "Command that runs before installing updates. The update is aborted if it fails.")),
+                ("post_update_command", typed("string", "Command that runs after updates are installed successfully")),
+                ("snapshot_before_update", typed("boolean", "Creates a snapshot before installing updates")),
+                ("snapshot_command", typed("string", "Command that creates the snapshot and prints its number. Falls back to `snapper create`.")),
+                ("include_reboot_patches", with_default(typed("boolean", "Also installs patches that require a reboot when updating without confirmation (zypper only). The system may need to be rebooted afterwards."), json!(false))),
+                ("cached_package_path", json!({
+                    "oneOf": [
+                        { "type": "string" },
+                        { "type": "array", "items": { "type": "string" } }
+                    ],
+                    "description": "Directory of the downloaded packages, or a remote `sftp://[user@]host[:port]/path` location, which is mirrored with rsync over SSH. Can also be a list of them."
+                })),
+                ("sudo_fallback", typed("boolean", "Uses sudo instead of pkexec when pkexec can't ask for a password")),
+                ("ignored_packages", json!({
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Package name patterns that are never updated, e.g. `kernel*`"
+                })),
+                ("reboot_triggers", with_default(json!({
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Package name patterns whose update requires a reboot. Only used when the package manager can't tell whether a reboot is required."
+                }), json!(["kernel*", "glibc", "systemd"]))),
+                ("rpm_path", typed("string", "Path of the rpm binary. Looked up in PATH if unset.")),
+                ("package_manager_path", typed("string", "Path of the package manager's binary for the commands package-assistant runs itself. Looked up in PATH if unset.")),
+                ("http_proxy", typed("string", "Proxy for HTTP connections of the spawned commands. Overrides the http_proxy environment variable.")),
+                ("https_proxy", typed("string", "Proxy for HTTPS connections of the spawned commands. Overrides the https_proxy environment variable.")),
+                ("no_proxy", typed("string", "Comma-separated hosts that are connected to without the proxy. Overrides the no_proxy environment variable.")),
+                ("output_locale", typed("string", "Locale that spawned commands run with, e.g. `C.UTF-8`")),
+                ("changelog_header_format", with_default(typed("string", "Header printed above each package's changelogs, where `{name}` is replaced by the package name"), json!("==== {name} ===="))),
+                ("changelog_separator", with_default(typed("string", "Printed between the changelogs of consecutive packages"), json!("\n\n"))),
+                ("changelog_date_format", with_default(typed("string", "strftime format of the date above each changelog entry"), json!(DEFAULT_CHANGELOG_DATE_FORMAT))),
+                ("changelog_timezone", typed("string", "Timezone of the changelog dates: `local`, `UTC`, or a fixed offset such as `+02:00`")),
+                ("filter_changelogs_by_installed", with_default(typed("boolean", "Leaves out the changelog entries of the installed versions and older ones"), json!(true))),
+                ("fuzzy_max_distance", with_default(typed("integer", "Number of typos allowed in changelog queries when fuzzy matching is enabled"), json!(2))),
+                ("scan_threads", typed("integer", "Maximum number of threads reading packages while scanning for changelogs. Zero means one per CPU."))
+            ])
+        }
+    })
+}
+
+fn object<const N: usize>(properties: [(&str, Value); N]) -> Value {
+    let properties = properties.into_iter()
+        .map(|(name, schema)| (name.to_owned(), schema))
+        .collect::<Map<String, Value>>();
+
+    json!({
+        "type": "object",
+        "properties": properties
+    })
+}
+
+fn typed(schema_type: &str, description: &str) -> Value {
+    json!({ "type": schema_type, "description": description })
+}
+
+fn with_default(mut schema: Value, default: Value) -> Value {
+    schema["default"] = default;
+    schema
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use serde_json::Value;
+
+    use crate::storage::Config;
+
+    use super::get_config_schema;
+
+    fn keys(object: &Value) -> BTreeSet<&str> {
+        object.as_object().unwrap().keys().map(String::as_str).collect()
+    }
+
+    /// Checks that the schema has a property for every setting in `config` and none that aren't settings, including
+    /// the `required` lists, and recurses into the tables
+    fn assert_schema_matches(schema: &Value, config: &Value, path: &str) {
+        let properties = &schema["properties"];
+        assert_eq!(keys(properties), keys(config), "schema properties of {} differ from the settings", path);
+
+        for required in schema["required"].as_array().into_iter().flatten() {
+            let required = required.as_str().unwrap();
+            assert!(config.get(required).is_some(), "{} requires unknown setting {}", path, required);
+        }
+
+        for (key, value) in config.as_object().unwrap() {
+            if value.is_object() {
+                assert_schema_matches(&properties[key], value, &format!("{}.{}", path, key));
+            }
+        }
+    }
+
+    #[test]
+    fn schema_matches_every_setting() {
+        // Unlike TOML, JSON keeps the settings that are unset as null, so every setting is listed
+        let config = serde_json::to_value(Config::default()).unwrap();
+        assert_schema_matches(&get_config_schema(), &config, "settings");
+    }
+}
\ No newline at end of file
@@ -1,4 +1,4 @@
-use std::{fs, path::PathBuf};
+use std::{ffi::OsString, fs, path::{Path, PathBuf}};
 
 use serde::{de::DeserializeOwned, Serialize};
 
@@ -6,6 +6,8 @@ use super::error::Error;
 
 const USER_HOME: &str = "HOME";
 const PROGRAM_NAME: &str = "package-assistant";
+const CACHE_HOME: &str = "XDG_CACHE_HOME";
+const DEFAULT_CACHE_PATH: &str = ".cache";
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -14,8 +16,10 @@ pub trait TomlStorage: Default + DeserializeOwned + Serialize {
         Default::default()
     }
 
+    /// Parses the TOML, migrating it if it was written by an older version
     fn from_toml_str(contents: &str) -> Result<Self> {
-        let data = toml::from_str::<Self>(contents)?;
+        let mut data = toml::from_str::<Self>(contents)?;
+        data.migrate();
         Ok(data)
     }
 
@@ -26,18 +30,60 @@ pub trait TomlStorage: Default + DeserializeOwned + Serialize {
 
     /// Gets the saved TOML file as a struct
     fn fetch() -> Result<Self> {
-        let path = Self::get_file_path()?;
+        Self::fetch_from(&Self::get_file_path()?)
+    }
+
+    /// Gets the TOML file at `path` as a struct
+    fn fetch_from(path: &Path) -> Result<Self> {
         let contents = fs::read_to_string(path)?;
         let data = Self::from_toml_str(contents.as_str())?;
 
         Ok(data)
     }
 
+    /// Like `fetch`, but gives the default struct if the file hasn't been saved yet. Files that can't be read or
+    /// parsed are still errors, so that saving the struct afterwards doesn't overwrite them.
+    fn fetch_or_default() -> Result<Self> {
+        Self::fetch_or_default_from(&Self::get_file_path()?)
+    }
+
+    /// Like `fetch_from`, but gives the default struct if there's no file at `path`
+    fn fetch_or_default_from(path: &Path) -> Result<Self> {
+        match Self::fetch_from(path) {
+            Err(Error::IO(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result
+        }
+    }
+
+    /// Rewrites the saved TOML file in the current format if it was written by an older version. Returns whether the
+    /// file was migrated.
+    fn migrate_file() -> Result<bool> {
+        let path = Self::get_file_path()?;
+        let contents = fs::read_to_string(path)?;
+        let mut data = toml::from_str::<Self>(&contents)?;
+
+        let migrated = data.migrate();
+        if migrated {
+            Self::save(data)?;
+        }
+
+        Ok(migrated)
+    }
+
     /// Saves the provided struct to the filesystem as TOML
     fn save(data: Self) -> Result<()> {
-        let path = Self::get_file_path()?;
+        Self::save_to(data, &Self::get_file_path()?)
+    }
+
+    /// Saves the provided struct as TOML to `path`, creating its directory if it doesn't exist yet, e.g. when data is
+    /// first saved by a command that runs before `init`
+    fn save_to(data: Self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
         let contents = data.to_toml_str()?;
-        fs::write(&path, contents)?;
+        fs::write(path, contents)?;
 
         Ok(())
     }
@@ -46,23 +92,7 @@ pub trait TomlStorage: Default + DeserializeOwned + Serialize {
     /// `Error::DirUndefined` if it is unable to resolve the directory using the existing
     /// environment variables.
     fn get_dir_path() -> Result<PathBuf> {
-        let home_dir = std::env::var_os(USER_HOME);
-        let data_home = std::env::var_os(Self::directory_env_var());
-
-        match data_home {
-            Some(c) if !c.is_empty() => Ok(PathBuf::from(c)),
-            _ => {
-                if let Some(home) = home_dir {
-                    let mut result = PathBuf::from(home);
-                    result.push(Self::default_directory());
-                    result.push(PROGRAM_NAME);
-
-                    Ok(result)
-                } else {
-                    Err(Error::DirUndefined)
-                }
-            }
-        }
+        get_xdg_dir_path(Self::directory_env_var(), Self::default_directory())
     }
 
     /// Gets the path that the file will be saved to
@@ -90,7 +120,7 @@ pub trait TomlStorage: Default + DeserializeOwned + Serialize {
             Self::save(data)?;
 
         //Create a fresh data file with the default settings
-        } if fs::exists(&file_path)? {
+        } else if fs::exists(&file_path)? {
             return Err(Error::FileAlreadyExists)
         } else {
             let data = Self::new();
@@ -100,6 +130,12 @@ pub trait TomlStorage: Default + DeserializeOwned + Serialize {
         Ok(file_path)
     }
 
+    /// Upgrades data written by an older version of package-assistant to the current format in memory. Returns
+    /// whether anything changed, in which case the file can be saved again to keep the migration.
+    fn migrate(&mut self) -> bool {
+        false
+    }
+
     fn file_name() -> &'static str;
 
     /// The environment variable to use to retrieve the parent directory of the file,
@@ -108,4 +144,73 @@ pub trait TomlStorage: Default + DeserializeOwned + Serialize {
 
     /// The fallback directory to save the file to relative to the user's home directory, e.g. .config
     fn default_directory() -> &'static str;
-}
\ No newline at end of file
+}
+
+/// Finds a directory as described in the XDG specification from the given environment variable, falling back to
+/// `default_directory` within the user's home directory
+pub fn get_xdg_dir_path(env_var: &str, default_directory: &str) -> Result<PathBuf> {
+    let home_dir = std::env::var_os(USER_HOME);
+    let data_home = std::env::var_os(env_var);
+
+    match data_home {
+        Some(c) if !c.is_empty() => Ok(PathBuf::from(c)),
+        _ => {
+            if let Some(home) = home_dir {
+                let mut result = PathBuf::from(home);
+                result.push(default_directory);
+                result.push(PROGRAM_NAME);
+
+                Ok(result)
+            } else {
+                Err(Error::DirUndefined)
+            }
+        }
+    }
+}
+
+/// Finds the directory for ephemeral files that can be recreated at any time, such as downloaded packages.
+/// This is a package-assistant subdirectory of `XDG_CACHE_HOME`, or of `~/.cache` if that isn't set.
+pub fn get_cache_dir_path() -> Result<PathBuf> {
+    resolve_cache_dir_path(std::env::var_os(CACHE_HOME), std::env::var_os(USER_HOME))
+}
+
+/// Resolves the cache directory from the values of `XDG_CACHE_HOME` and `HOME`
+fn resolve_cache_dir_path(cache_home: Option<OsString>, home: Option<OsString>) -> Result<PathBuf> {
+    let mut path = match cache_home {
+        Some(c) if !c.is_empty() => PathBuf::from(c),
+        _ => {
+            let home = home.ok_or(Error::DirUndefined)?;
+            let mut result = PathBuf::from(home);
+            result.push(DEFAULT_CACHE_PATH);
+
+            result
+        }
+    };
+    path.push(PROGRAM_NAME);
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_dir_is_under_xdg_cache_home() {
+        let path = resolve_cache_dir_path(Some(OsString::from("/var/cache/user")), Some(OsString::from("/home/user")));
+        assert_eq!(path.unwrap(), Path::new("/var/cache/user/package-assistant"));
+    }
+
+    #[test]
+    fn cache_dir_falls_back_to_home_when_xdg_cache_home_is_unset_or_empty() {
+        for cache_home in [None, Some(OsString::new())] {
+            let path = resolve_cache_dir_path(cache_home, Some(OsString::from("/home/user")));
+            assert_eq!(path.unwrap(), Path::new("/home/user/.cache/package-assistant"));
+        }
+    }
+
+    #[test]
+    fn cache_dir_is_undefined_without_any_directory() {
+        assert!(matches!(resolve_cache_dir_path(None, None), Err(Error::DirUndefined)));
+    }
+}